@@ -0,0 +1,116 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use crate::Error;
+
+// how a heightmap layer is combined with the layers before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    #[default]
+    Add,
+    Max,
+    Min,
+    // scale the heights below by this layer, white keeps them and black flattens them
+    Multiply,
+    // add this layer's difference from middle gray, raising and lowering the heights below
+    Detail,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 5] = [
+        BlendMode::Add,
+        BlendMode::Max,
+        BlendMode::Min,
+        BlendMode::Multiply,
+        BlendMode::Detail,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BlendMode::Add => "add",
+            BlendMode::Max => "max",
+            BlendMode::Min => "min",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Detail => "detail",
+        }
+    }
+}
+
+// the mode and weight of a heightmap layer, the first layer is the base and only uses its weight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Blend {
+    pub mode: BlendMode,
+    // multiplies the layer's heights, or how strongly it multiplies the layers below
+    pub weight: f32,
+}
+
+impl Default for Blend {
+    fn default() -> Self {
+        Blend::new(BlendMode::Add)
+    }
+}
+
+impl Blend {
+    // detail layers default to a quarter of their height
+    pub fn new(mode: BlendMode) -> Self {
+        Blend {
+            mode,
+            weight: if mode == BlendMode::Detail { 0.25 } else { 1.0 },
+        }
+    }
+
+    // combine a layer height with the blended height of the layers before it,
+    // max is the largest height a layer can have
+    pub fn apply(&self, below: f64, height: f64, max: f64) -> f64 {
+        let weight = self.weight as f64;
+        match self.mode {
+            BlendMode::Add => below + height * weight,
+            BlendMode::Max => below.max(height * weight),
+            BlendMode::Min => below.min(height * weight),
+            BlendMode::Multiply => below * (1.0 - weight + weight * height / max),
+            BlendMode::Detail => below + (height - max / 2.0) * weight,
+        }
+    }
+}
+
+impl Hash for Blend {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.mode.hash(state);
+        self.weight.to_bits().hash(state);
+    }
+}
+
+// parse a mode with an optional weight, such as detail:0.3
+impl FromStr for Blend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, weight) = match s.split_once(':') {
+            Some((mode, weight)) => (mode, Some(weight)),
+            None => (s, None),
+        };
+        let mode = BlendMode::ALL
+            .into_iter()
+            .find(|m| m.name() == mode.trim().to_lowercase())
+            .ok_or_else(|| Error::UnknownBlend(s.to_string()))?;
+        let mut blend = Blend::new(mode);
+        if let Some(weight) = weight {
+            blend.weight = weight
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|w| w.is_finite())
+                .ok_or_else(|| Error::UnknownBlend(s.to_string()))?;
+        }
+        Ok(blend)
+    }
+}
+
+impl Display for Blend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.mode.name(), self.weight)
+    }
+}
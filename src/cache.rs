@@ -0,0 +1,76 @@
+use std::{
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, map::Source};
+
+// 64 bit FNV-1a hasher, stable between builds unlike the std DefaultHasher
+pub struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Fnv64(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv64 {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+// hash the contents of every input along with the generator options
+pub fn input_hash(sources: &[&Source], options: &impl Hash) -> Result<u64, Error> {
+    let mut hasher = Fnv64::default();
+
+    // a new version of the generator may produce different output
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    options.hash(&mut hasher);
+
+    for source in sources {
+        match source {
+            Source::File(path) => fs::read(path)
+                .map_err(|source| Error::ReadFile {
+                    path: path.clone(),
+                    source,
+                })?
+                .hash(&mut hasher),
+            Source::Bytes(bytes) => bytes.hash(&mut hasher),
+            Source::Image(img) => {
+                (img.width(), img.height()).hash(&mut hasher);
+                img.as_bytes().hash(&mut hasher);
+            }
+        }
+    }
+
+    Ok(hasher.finish())
+}
+
+// path of the hash file stored next to an output save
+pub fn hash_path(out_file: impl AsRef<Path>) -> PathBuf {
+    let mut path = out_file.as_ref().as_os_str().to_owned();
+    path.push(".hash");
+    PathBuf::from(path)
+}
+
+// determine if an output save was already generated from the same inputs
+pub fn is_up_to_date(out_file: impl AsRef<Path>, hash: u64) -> bool {
+    out_file.as_ref().exists()
+        && fs::read_to_string(hash_path(&out_file))
+            .is_ok_and(|stored| stored.trim() == format!("{hash:016x}"))
+}
+
+// record the input hash next to an output save
+pub fn write_hash(out_file: impl AsRef<Path>, hash: u64) -> Result<(), Error> {
+    let path = hash_path(out_file);
+    fs::write(&path, format!("{hash:016x}\n")).map_err(|source| Error::WriteFile { path, source })
+}
@@ -0,0 +1,181 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+use brdb::Collision;
+
+use crate::Error;
+
+// which collision channels a brick has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Channels {
+    // players walk on the brick
+    pub player: bool,
+    // projectiles hit the brick
+    pub weapon: bool,
+    // the brick can be clicked and used
+    pub interact: bool,
+    // the brick can be selected with building tools
+    pub tool: bool,
+    // physics objects bounce off the brick
+    pub physics: bool,
+}
+
+impl Channels {
+    pub const ALL: Channels = Channels {
+        player: true,
+        weapon: true,
+        interact: true,
+        tool: true,
+        physics: true,
+    };
+    pub const NONE: Channels = Channels {
+        player: false,
+        weapon: false,
+        interact: false,
+        tool: false,
+        physics: false,
+    };
+    // bricks that can be walked through but are still buildable
+    pub const NOCOLLIDE: Channels = Channels {
+        tool: true,
+        physics: true,
+        ..Channels::NONE
+    };
+
+    fn names(&self) -> [(&'static str, bool); 5] {
+        [
+            ("player", self.player),
+            ("weapon", self.weapon),
+            ("interact", self.interact),
+            ("tool", self.tool),
+            ("physics", self.physics),
+        ]
+    }
+}
+
+impl From<Channels> for Collision {
+    fn from(c: Channels) -> Self {
+        Collision {
+            player: c.player,
+            weapon: c.weapon,
+            interact: c.interact,
+            tool: c.tool,
+            physics: c.physics,
+        }
+    }
+}
+
+// parse "all", "none", or a comma separated list of channel names
+impl FromStr for Channels {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "all" => return Ok(Channels::ALL),
+            "none" | "" => return Ok(Channels::NONE),
+            _ => {}
+        }
+        let mut channels = Channels::NONE;
+        for name in s.split(',').map(|n| n.trim().to_lowercase()) {
+            let channel = match name.as_str() {
+                "player" => &mut channels.player,
+                "weapon" => &mut channels.weapon,
+                "interact" => &mut channels.interact,
+                "tool" => &mut channels.tool,
+                "physics" => &mut channels.physics,
+                _ => return Err(Error::UnknownChannel(name)),
+            };
+            *channel = true;
+        }
+        Ok(channels)
+    }
+}
+
+impl Display for Channels {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Channels::ALL => write!(f, "all"),
+            Channels::NONE => write!(f, "none"),
+            _ => {
+                let names = self
+                    .names()
+                    .into_iter()
+                    .filter_map(|(name, on)| on.then_some(name))
+                    .collect::<Vec<_>>();
+                write!(f, "{}", names.join(","))
+            }
+        }
+    }
+}
+
+// collision channels for the whole map and for regions of it,
+// such as no collision on water or on decorative peaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CollisionRules {
+    // channels of bricks outside every region
+    pub channels: Channels,
+    // pixels lower than this height
+    pub below: Option<(u32, Channels)>,
+    // pixels higher than this height
+    pub above: Option<(u32, Channels)>,
+    // pixels that are white in the collision mask
+    pub mask: Channels,
+}
+
+impl Default for CollisionRules {
+    fn default() -> Self {
+        CollisionRules {
+            channels: Channels::ALL,
+            below: None,
+            above: None,
+            mask: Channels::NONE,
+        }
+    }
+}
+
+impl CollisionRules {
+    // the channels of a pixel, the mask takes priority over the height regions
+    pub fn at(&self, height: u32, in_mask: bool) -> Channels {
+        if in_mask {
+            return self.mask;
+        }
+        match (self.below, self.above) {
+            (Some((level, channels)), _) if height < level => channels,
+            (_, Some((level, channels))) if height > level => channels,
+            _ => self.channels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_then_below_then_above() {
+        let water = "none".parse::<Channels>().unwrap();
+        let peaks = "tool,physics".parse::<Channels>().unwrap();
+        let rules = CollisionRules {
+            below: Some((12, water)),
+            above: Some((200, peaks)),
+            mask: Channels::NOCOLLIDE,
+            ..Default::default()
+        };
+        // a pixel in the mask and below the water level gets the mask channels
+        assert_eq!(rules.at(5, true), Channels::NOCOLLIDE);
+        assert_eq!(rules.at(5, false), water);
+        assert_eq!(rules.at(250, true), Channels::NOCOLLIDE);
+        assert_eq!(rules.at(250, false), peaks);
+        assert_eq!(rules.at(100, false), Channels::ALL);
+
+        // with overlapping levels, below is checked before above
+        let overlap = CollisionRules {
+            below: Some((100, water)),
+            above: Some((10, peaks)),
+            ..rules
+        };
+        assert_eq!(overlap.at(50, false), water);
+    }
+}
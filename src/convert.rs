@@ -0,0 +1,837 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use brdb::World;
+use log::info;
+
+use crate::{
+    Error,
+    cache::{input_hash, is_up_to_date, write_hash},
+    map::{
+        Colormap, ColormapPNG, Cropped, Heightmap, HeightmapFlat, HeightmapPNG, Mask, MaskPNG,
+        Source, Transformed,
+    },
+    merge::{Merge, merge_bricks, read_world},
+    mesh::Mesh,
+    meta::{SaveMeta, Screenshot, colormap_screenshot, render_screenshot},
+    quad::{gen_opt_heightmap, optimize_heightmap},
+    remap::Remapped,
+    render::{Camera, render_bricks},
+    resample::{resample_colormap, resample_heightmap, resample_mask},
+    util::{GenOptions, bricks_to_save, file_ext},
+};
+
+// the maps bricks are generated from, all with the same size
+pub struct Maps {
+    pub heightmap: Box<dyn Heightmap>,
+    pub colormap: Box<dyn Colormap>,
+    pub mask: Option<Box<dyn Mask>>,
+    // pixels that get the collision mask channels
+    pub collision_mask: Option<Box<dyn Mask>>,
+    // the heightmap without smoothing, when smoothing is compared
+    pub unsmoothed: Option<Box<dyn Heightmap>>,
+}
+
+// read heightmap, colormap and mask sources into maps, the colormap defaults to the first heightmap
+pub fn maps_from_sources(
+    options: &GenOptions,
+    heightmaps: &[Source],
+    colormap: Option<&Source>,
+    mask: Option<&Source>,
+    collision_mask: Option<&Source>,
+) -> Result<Maps, Error> {
+    let Some(colormap_source) = colormap.or(heightmaps.first()) else {
+        return Err(Error::NoHeightmaps);
+    };
+
+    // colormap file parsing
+    if let Source::File(path) = colormap_source {
+        match file_ext(path) {
+            Some("png") => {}
+            Some(ext) => {
+                return Err(Error::UnsupportedFormat {
+                    kind: "colormap",
+                    ext: ext.to_string(),
+                });
+            }
+            None => {
+                return Err(Error::MissingFormat {
+                    kind: "colormap",
+                    path: path.clone(),
+                });
+            }
+        }
+    }
+    let colormap = ColormapPNG::from_source(colormap_source, options.lrgb)?;
+
+    // heightmap file parsing
+    if let Some(ext) = heightmaps.iter().find_map(|source| match source {
+        Source::File(path) => file_ext(path).filter(|ext| *ext != "png"),
+        _ => None,
+    }) {
+        return Err(Error::UnsupportedFormat {
+            kind: "heightmap",
+            ext: ext.to_string(),
+        });
+    }
+    let mut heightmap: Box<dyn Heightmap> = if options.img {
+        Box::new(HeightmapFlat::new(colormap.size())?)
+    } else {
+        Box::new(
+            HeightmapPNG::from_sources(heightmaps, options.decoding)?
+                .elevation(options.elevation)
+                .blend(options.blends.clone())?,
+        )
+    };
+    let mut colormap: Box<dyn Colormap> = Box::new(colormap);
+    // the generation mask and the collision mask go through the same steps
+    let load_mask = |source: Option<&Source>| -> Result<Option<Box<dyn Mask>>, Error> {
+        Ok(match source {
+            Some(source) => Some(Box::new(MaskPNG::from_source(source)?)),
+            None => None,
+        })
+    };
+    let mut masks = [load_mask(mask)?, load_mask(collision_mask)?];
+
+    // crop before stretching the colormap so only the kept region is resampled
+    if let Some(crop) = options.crop {
+        let (size, color_size) = (heightmap.size(), colormap.size());
+        heightmap = Box::new(Cropped::new(heightmap, crop, size)?);
+        colormap = Box::new(Cropped::new(
+            colormap,
+            crop.scaled(size, color_size),
+            color_size,
+        )?);
+        for mask in &mut masks {
+            if let Some(m) = mask.take() {
+                let mask_size = m.size();
+                *mask = Some(Box::new(Cropped::new(
+                    m,
+                    crop.scaled(size, mask_size),
+                    mask_size,
+                )?));
+            }
+        }
+    }
+
+    // stretch a colormap to the heightmap if their sizes are different
+    if colormap.size() != heightmap.size() {
+        info!(
+            "Resizing colormap from {:?} to {:?}",
+            colormap.size(),
+            heightmap.size()
+        );
+        colormap = Box::new(resample_colormap(
+            &*colormap,
+            heightmap.size(),
+            options.color_filter,
+        ));
+    }
+    for m in masks.iter_mut().flatten() {
+        if m.size() != heightmap.size() {
+            info!(
+                "Resizing mask from {:?} to {:?}",
+                m.size(),
+                heightmap.size()
+            );
+            *m = Box::new(resample_mask(&**m, heightmap.size(), options.color_filter));
+        }
+    }
+
+    if !options.transform.is_identity() {
+        heightmap = Box::new(Transformed::new(heightmap, options.transform));
+        colormap = Box::new(Transformed::new(colormap, options.transform));
+        masks = masks.map(|mask| {
+            mask.map(|m| Box::new(Transformed::new(m, options.transform)) as Box<dyn Mask>)
+        });
+    }
+
+    if let Some(resize) = options.resize {
+        let size = resize.pixels(heightmap.size(), options.size);
+        if size != heightmap.size() {
+            info!("Resampling maps from {:?} to {size:?}", heightmap.size());
+            heightmap = Box::new(resample_heightmap(&*heightmap, size, options.height_filter));
+            colormap = Box::new(resample_colormap(&*colormap, size, options.color_filter));
+            masks = masks.map(|mask| {
+                mask.map(|m| {
+                    Box::new(resample_mask(&*m, size, options.color_filter)) as Box<dyn Mask>
+                })
+            });
+        }
+    }
+
+    let mut unsmoothed = None;
+    if !options.img
+        && let Some(smoothed) = options.smooth.apply(&*heightmap)
+    {
+        let unfiltered = std::mem::replace(&mut heightmap, smoothed);
+        if options.smooth.compare {
+            unsmoothed = Some(unfiltered);
+        }
+    }
+
+    if !options.img && !options.remap.is_identity() {
+        let remapped = Remapped::new(heightmap, options.remap.clone());
+        info!("Remapping heights from {:?}", remapped.range());
+        heightmap = Box::new(remapped);
+        unsmoothed = unsmoothed
+            .map(|h| Box::new(Remapped::new(h, options.remap.clone())) as Box<dyn Heightmap>);
+    }
+
+    let [mask, collision_mask] = masks;
+    Ok(Maps {
+        heightmap,
+        colormap,
+        mask,
+        collision_mask,
+        unsmoothed,
+    })
+}
+
+// an output file format, either a save or a mesh for other 3D software
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Brz,
+    Brdb,
+    Obj,
+    Stl,
+    Gltf,
+    Glb,
+}
+
+impl Format {
+    // pick the output format from the file extension
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        file_ext(path.as_ref())
+            .ok_or(Error::UnsupportedOutput)?
+            .parse()
+    }
+
+    // formats that are meshes instead of brickadia saves
+    pub fn is_mesh(self) -> bool {
+        !matches!(self, Format::Brz | Format::Brdb)
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "brz" => Ok(Format::Brz),
+            "brdb" => Ok(Format::Brdb),
+            "obj" => Ok(Format::Obj),
+            "stl" => Ok(Format::Stl),
+            "gltf" => Ok(Format::Gltf),
+            "glb" => Ok(Format::Glb),
+            _ => Err(Error::UnsupportedOutput),
+        }
+    }
+}
+
+// encode a save into any writer, brdb saves are databases and can only be written to files
+pub fn write_save(world: &World, format: Format, writer: &mut impl Write) -> Result<(), Error> {
+    match format {
+        Format::Brz => writer.write_all(&world.to_brz_vec()?)?,
+        Format::Brdb => return Err(Error::BrdbStream),
+        Format::Obj => Mesh::from_bricks(&world.bricks).write_obj(writer)?,
+        Format::Stl => Mesh::from_bricks(&world.bricks).write_stl(writer)?,
+        Format::Gltf => Mesh::from_bricks(&world.bricks).write_gltf(writer)?,
+        Format::Glb => Mesh::from_bricks(&world.bricks).write_glb(writer)?,
+    }
+    Ok(())
+}
+
+// a save destination
+pub enum Output {
+    File(PathBuf, Format),
+    Writer(Box<dyn Write + Send>, Format),
+}
+
+impl Output {
+    // a file output with the format picked from the file extension
+    pub fn from_path(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let format = Format::from_path(&path)?;
+        Ok(Output::File(path, format))
+    }
+
+    // a stream output, brdb saves can only be written to files
+    pub fn writer(writer: impl Write + Send + 'static, format: Format) -> Result<Self, Error> {
+        let output = Output::Writer(Box::new(writer), format);
+        output.validate()?;
+        Ok(output)
+    }
+
+    // check that the format can be written to this destination
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            Output::Writer(_, Format::Brdb) => Err(Error::BrdbStream),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Output::File(path, _) => Some(path),
+            Output::Writer(..) => None,
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            Output::File(_, format) | Output::Writer(_, format) => *format,
+        }
+    }
+
+    // override the format picked for this output
+    pub fn with_format(self, format: Format) -> Result<Self, Error> {
+        let output = match self {
+            Output::File(path, _) => Output::File(path, format),
+            Output::Writer(writer, _) => Output::Writer(writer, format),
+        };
+        output.validate()?;
+        Ok(output)
+    }
+
+    // write a save to this destination
+    pub fn write(self, world: &World) -> Result<(), Error> {
+        match self {
+            Output::File(path, Format::Brdb) => world.write_brdb(path)?,
+            Output::File(path, format) => {
+                write_save(world, format, &mut BufWriter::new(File::create(path)?))?
+            }
+            Output::Writer(mut writer, format) => {
+                write_save(world, format, &mut writer)?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Output {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::File(path, _) => write!(f, "{}", path.display()),
+            Output::Writer(..) => write!(f, "stream"),
+        }
+    }
+}
+
+// a 3D render of the generated bricks saved as an image
+#[derive(Debug, Clone)]
+pub struct Render {
+    pub path: PathBuf,
+    pub camera: Camera,
+    pub size: (u32, u32),
+}
+
+// result of a conversion
+#[derive(Debug, Default)]
+pub struct Summary {
+    // the output was left untouched because its inputs have not changed
+    pub skipped: bool,
+    // dimensions of the input maps
+    pub size: (u32, u32),
+    // number of bricks in the generated save
+    pub bricks: usize,
+    // how many fewer bricks were generated because of smoothing,
+    // negative if smoothing made more bricks, only when smoothing is compared
+    pub smoothing_merges: Option<isize>,
+}
+
+// Converts heightmap and colormap images into a save file
+pub struct Converter {
+    options: GenOptions,
+    heightmaps: Vec<Source>,
+    colormap: Option<Source>,
+    mask: Option<Source>,
+    collision_mask: Option<Source>,
+    output: Output,
+    skip_unchanged: bool,
+    render: Option<Render>,
+    merge: Option<Merge>,
+    meta: SaveMeta,
+}
+
+impl Converter {
+    pub fn new(options: GenOptions, output: Output) -> Self {
+        Converter {
+            options,
+            heightmaps: vec![],
+            colormap: None,
+            mask: None,
+            collision_mask: None,
+            output,
+            skip_unchanged: false,
+            render: None,
+            merge: None,
+            meta: SaveMeta::default(),
+        }
+    }
+
+    pub fn heightmaps<S: Into<Source>>(mut self, sources: impl IntoIterator<Item = S>) -> Self {
+        self.heightmaps = sources.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn colormap(mut self, source: Option<impl Into<Source>>) -> Self {
+        self.colormap = source.map(Into::into);
+        self
+    }
+
+    // only generate the pixels that are white in the mask
+    pub fn mask(mut self, source: Option<impl Into<Source>>) -> Self {
+        self.mask = source.map(Into::into);
+        self
+    }
+
+    // give the pixels that are white in the mask the collision mask channels
+    pub fn collision_mask(mut self, source: Option<impl Into<Source>>) -> Self {
+        self.collision_mask = source.map(Into::into);
+        self
+    }
+
+    // skip generation if the output was already created from the same inputs
+    pub fn skip_unchanged(mut self, skip: bool) -> Self {
+        self.skip_unchanged = skip;
+        self
+    }
+
+    // also render the generated bricks to an image
+    pub fn render(mut self, render: Option<Render>) -> Self {
+        self.render = render;
+        self
+    }
+
+    // add the generated bricks to an existing save instead of a new one
+    pub fn merge(mut self, merge: Option<Merge>) -> Self {
+        self.merge = merge;
+        self
+    }
+
+    // set the name, description, author and preview image of the save
+    pub fn meta(mut self, meta: SaveMeta) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    // hash the contents of every input along with the options that change the save
+    fn input_hash(&self) -> Result<u64, Error> {
+        let merge_source = self.merge.as_ref().map(|m| Source::File(m.path.clone()));
+        let inputs = self
+            .heightmaps
+            .iter()
+            .chain(self.colormap.as_ref())
+            .chain(self.mask.as_ref())
+            .chain(self.collision_mask.as_ref())
+            .chain(merge_source.as_ref())
+            .chain(self.meta.screenshot_source())
+            .collect::<Vec<_>>();
+        input_hash(&inputs, &(&self.options, &self.merge, &self.meta))
+    }
+
+    // the command line that generates the same bricks, recorded in the save description
+    fn args(&self) -> String {
+        let name = |source: &Source| match source {
+            Source::File(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+            _ => "-".to_string(),
+        };
+        let mut args = vec!["heightmap".to_string()];
+        args.extend(self.heightmaps.iter().map(name));
+        if let Some(colormap) = &self.colormap {
+            args.push(format!("-c {}", name(colormap)));
+        }
+        if let Some(mask) = &self.mask {
+            args.push(format!("--mask {}", name(mask)));
+        }
+        if let Some(mask) = &self.collision_mask {
+            args.push(format!("--collision-mask {}", name(mask)));
+        }
+        args.extend(self.options.to_args());
+        if let Some(merge) = &self.merge {
+            args.push(format!(
+                "--merge {}",
+                name(&Source::File(merge.path.clone()))
+            ));
+            if let Some(owner) = &merge.owner {
+                args.push(format!("--merge-owner \"{owner}\""));
+            }
+            if merge.remove_overlap {
+                args.push("--remove-overlap".to_string());
+            }
+            if merge.drop_components {
+                args.push("--drop-components".to_string());
+            }
+        }
+        args.join(" ")
+    }
+
+    // run the conversion, progress_f receives the current step and overall progress
+    // and returns false to stop the conversion
+    pub fn run<F: Fn(&'static str, f32) -> bool>(self, progress_f: F) -> Result<Summary, Error> {
+        macro_rules! progress {
+            ($s:expr, $e:expr) => {
+                if !progress_f($s, $e) {
+                    return Err(Error::Stopped);
+                }
+            };
+        }
+        progress!("Reading", 0.0);
+        self.output.validate()?;
+
+        // hash the inputs to determine if the output needs to be regenerated,
+        // otherwise the hash is only computed when it's written next to the save
+        let mut hash = None;
+        if let (true, Some(path)) = (self.skip_unchanged, self.output.path()) {
+            let inputs = self.input_hash()?;
+            if is_up_to_date(path, inputs) {
+                info!("{} is up to date, skipping", path.display());
+                return Ok(Summary {
+                    skipped: true,
+                    ..Default::default()
+                });
+            }
+            hash = Some(inputs);
+        }
+
+        // read the save to merge into first so a bad save fails before generating
+        let base = match &self.merge {
+            Some(merge) => {
+                info!("Reading save {}...", merge.path.display());
+                Some(read_world(&merge.path, merge.drop_components)?)
+            }
+            None => None,
+        };
+
+        info!("Reading image files...");
+        let maps = maps_from_sources(
+            &self.options,
+            &self.heightmaps,
+            self.colormap.as_ref(),
+            self.mask.as_ref(),
+            self.collision_mask.as_ref(),
+        )?;
+        progress!("Generating", 0.1);
+
+        let bricks = gen_opt_heightmap(
+            &*maps.heightmap,
+            &*maps.colormap,
+            maps.mask.as_deref(),
+            maps.collision_mask.as_deref(),
+            self.options.clone(),
+            |p| progress_f("Generating", 0.1 + 0.85 * p),
+        )?;
+
+        // generate again without smoothing to see how much it helped
+        let mut smoothing_merges = None;
+        if let Some(unsmoothed) = &maps.unsmoothed {
+            info!("Generating without smoothing for comparison...");
+            let quad = optimize_heightmap(
+                &**unsmoothed,
+                &*maps.colormap,
+                maps.mask.as_deref(),
+                maps.collision_mask.as_deref(),
+                &self.options,
+                |_| progress_f("Comparing", 0.95),
+            )?;
+            let without = quad.into_bricks(self.options.clone()).len();
+            let merges = without as isize - bricks.len() as isize;
+            if merges >= 0 {
+                info!(
+                    "Smoothing enabled {merges} extra merges ({without} bricks without smoothing)"
+                );
+            } else {
+                info!(
+                    "Smoothing prevented {} merges ({without} bricks without smoothing)",
+                    -merges
+                );
+            }
+            smoothing_merges = Some(merges);
+        }
+
+        if let Some(render) = &self.render {
+            info!("Rendering bricks to {}", render.path.display());
+            render_bricks(&bricks, &render.camera, render.size)
+                .save(&render.path)
+                .map_err(|source| Error::WriteImage {
+                    path: render.path.clone(),
+                    source,
+                })?;
+        }
+
+        // meshes have no preview image
+        let screenshot = match (&self.meta.screenshot, &base) {
+            _ if self.output.format().is_mesh() => None,
+            (None, Some(_)) | (Some(Screenshot::None), _) => None,
+            (None, None) | (Some(Screenshot::Colormap), _) => Some(colormap_screenshot(
+                &*maps.colormap,
+                maps.mask.as_deref(),
+                self.options.size,
+            )),
+            (Some(Screenshot::Render), _) => Some(render_screenshot(
+                &bricks,
+                maps.heightmap.size(),
+                self.options.size,
+            )),
+            (Some(Screenshot::Image(source)), _) => Some(source.load()?),
+        };
+
+        info!("Writing Save to {}", self.output);
+        progress!("Writing", 0.95);
+        let summary = Summary {
+            skipped: false,
+            size: maps.heightmap.size(),
+            bricks: bricks.len(),
+            smoothing_merges,
+        };
+        // hash the inputs for the hash file now if skipping didn't already
+        let hash = match (self.output.path(), hash) {
+            (Some(path), Some(hash)) => Some((path.to_path_buf(), hash)),
+            (Some(path), None) => Some((path.to_path_buf(), self.input_hash()?)),
+            (None, _) => None,
+        };
+        let args = self.args();
+        let merged = base.is_some();
+        let mut world = match (base, &self.merge) {
+            (Some(mut world), Some(merge)) => {
+                merge_bricks(&mut world, bricks, merge, self.options.owner.as_ref())?;
+                world
+            }
+            _ => bricks_to_save(bricks, self.options.owner.as_ref()),
+        };
+        self.meta.apply(&mut world, &args, merged, screenshot)?;
+        self.output.write(&world)?;
+        if let Some((path, hash)) = hash {
+            write_hash(path, hash)?;
+        }
+
+        progress!("Finished", 1.0);
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use brdb::{Brick, BrickType, Brz, IntoReader};
+    use image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+
+    use super::*;
+    use crate::{
+        blend::{Blend, BlendMode},
+        merge::read_reader,
+        smooth::Smooth,
+    };
+
+    // a writer that can be read after the converter takes ownership of it
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn gray(width: u32, height: u32, f: impl Fn(u32, u32) -> u8) -> Source {
+        Source::Image(DynamicImage::ImageLuma8(GrayImage::from_fn(
+            width,
+            height,
+            |x, y| Luma([f(x, y)]),
+        )))
+    }
+
+    // convert into memory and read the brz back
+    fn run(converter: impl FnOnce(Output) -> Converter) -> (Summary, World) {
+        let out = Shared::default();
+        let summary = converter(Output::writer(out.clone(), Format::Brz).unwrap())
+            .run(|_, _| true)
+            .unwrap();
+        let bytes = out.0.lock().unwrap().clone();
+        let world = read_reader(&Brz::read_slice(&bytes).unwrap().into_reader(), false).unwrap();
+        (summary, world)
+    }
+
+    fn size(brick: &Brick) -> (u16, u16, u16) {
+        match &brick.asset {
+            BrickType::Procedural { size, .. } => (size.x, size.y, size.z),
+            BrickType::Basic(_) => panic!("expected a procedural brick"),
+        }
+    }
+
+    #[test]
+    fn flat_map_is_one_brick() {
+        let (summary, world) = run(|output| {
+            Converter::new(GenOptions::default(), output).heightmaps([gray(8, 8, |_, _| 10)])
+        });
+        assert!(!summary.skipped);
+        assert_eq!(summary.size, (8, 8));
+        assert_eq!(summary.bricks, 1);
+        assert_eq!(world.bricks.len(), 1);
+        // one brick covers the whole 8x8 stud map
+        let (x, y, _) = size(&world.bricks[0]);
+        assert_eq!((x, y), (40, 40));
+    }
+
+    #[test]
+    fn steps_and_colormap() {
+        // two flat halves at different heights, colored red and blue
+        let colormap = RgbaImage::from_fn(8, 8, |x, _| {
+            Rgba(if x < 4 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 255]
+            })
+        });
+        let (summary, world) = run(|output| {
+            Converter::new(GenOptions::default(), output)
+                .heightmaps([gray(8, 8, |x, _| if x < 4 { 10 } else { 20 })])
+                .colormap(Some(colormap))
+        });
+        assert_eq!(summary.size, (8, 8));
+        assert_eq!(summary.bricks, 2);
+        assert_eq!(world.bricks.len(), 2);
+
+        let (low, high) = match world.bricks[0].position.z < world.bricks[1].position.z {
+            true => (&world.bricks[0], &world.bricks[1]),
+            false => (&world.bricks[1], &world.bricks[0]),
+        };
+        assert_eq!((size(low).0, size(low).1), (20, 40));
+        assert_eq!((size(high).0, size(high).1), (20, 40));
+        assert!(low.position.x < high.position.x);
+        assert!(low.color.r > 250 && low.color.b == 0);
+        assert!(high.color.b > 250 && high.color.r == 0);
+    }
+
+    #[test]
+    fn smoothing_is_compared_on_request() {
+        // a flat map with a few single pixel spikes, despeckling lets it merge into one brick
+        let spikes = [(1, 1), (5, 2), (3, 5), (6, 6)];
+        let heightmap = gray(8, 8, |x, y| if spikes.contains(&(x, y)) { 60 } else { 10 });
+        // a flat colormap, the heightmap would give the spikes their own color
+        let colormap = gray(8, 8, |_, _| 128);
+        let options = |compare| GenOptions {
+            smooth: Smooth {
+                despeckle: true,
+                compare,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (summary, _) = run(|output| {
+            Converter::new(options(false), output)
+                .heightmaps([heightmap.clone()])
+                .colormap(Some(colormap.clone()))
+        });
+        assert_eq!(summary.smoothing_merges, None);
+
+        let (compared, _) = run(|output| {
+            Converter::new(options(true), output)
+                .heightmaps([heightmap.clone()])
+                .colormap(Some(colormap.clone()))
+        });
+        assert_eq!(compared.bricks, 1);
+        assert_eq!(compared.bricks, summary.bricks);
+        assert!(compared.smoothing_merges.unwrap() > 0);
+    }
+
+    #[test]
+    fn culled_pixels_are_not_counted() {
+        // the bottom half is at height 0 and culled
+        let options = GenOptions {
+            cull: true,
+            ..Default::default()
+        };
+        let (summary, world) = run(|output| {
+            Converter::new(options, output).heightmaps([gray(
+                4,
+                8,
+                |_, y| if y < 4 { 6 } else { 0 },
+            )])
+        });
+        assert_eq!(summary.size, (4, 8));
+        assert_eq!(summary.bricks, 1);
+        assert_eq!(world.bricks.len(), 1);
+    }
+
+    #[test]
+    fn brdb_cannot_be_streamed() {
+        assert!(matches!(
+            Output::writer(Shared::default(), Format::Brdb),
+            Err(Error::BrdbStream)
+        ));
+        let output = Output::writer(Shared::default(), Format::Brz).unwrap();
+        assert!(matches!(
+            output.with_format(Format::Brdb),
+            Err(Error::BrdbStream)
+        ));
+
+        // fails before reading any inputs
+        let result = Converter::new(
+            GenOptions::default(),
+            Output::Writer(Box::new(Shared::default()), Format::Brdb),
+        )
+        .heightmaps(["missing.png"])
+        .run(|_, _| true);
+        assert!(matches!(result, Err(Error::BrdbStream)));
+    }
+
+    #[test]
+    fn missing_heightmaps_error() {
+        let output = Output::writer(Shared::default(), Format::Brz).unwrap();
+        let result = Converter::new(GenOptions::default(), output).run(|_, _| true);
+        assert!(matches!(result, Err(Error::NoHeightmaps)));
+    }
+
+    #[test]
+    fn missing_heightmap_names_the_file() {
+        // writing to a file records an input hash, the image reader still reports the error
+        let dir = std::env::temp_dir();
+        let missing = dir.join("heightmap_missing_input.png");
+        let output = Output::File(dir.join("heightmap_missing_input.brz"), Format::Brz);
+        let result = Converter::new(GenOptions::default(), output)
+            .heightmaps([missing.clone()])
+            .run(|_, _| true);
+        assert!(matches!(result, Err(Error::Image { path, .. }) if path == missing));
+    }
+
+    #[test]
+    fn blends_match_heightmaps() {
+        let options = GenOptions {
+            blends: vec![Blend::default(), Blend::new(BlendMode::Max)],
+            ..Default::default()
+        };
+        let output = Output::writer(Shared::default(), Format::Brz).unwrap();
+        let result = Converter::new(options.clone(), output)
+            .heightmaps([gray(4, 4, |_, _| 10)])
+            .run(|_, _| true);
+        assert!(matches!(
+            result,
+            Err(Error::MismatchedBlends {
+                blends: 2,
+                heightmaps: 1
+            })
+        ));
+
+        let (summary, _) = run(|output| {
+            Converter::new(options, output)
+                .heightmaps([gray(4, 4, |_, _| 10), gray(4, 4, |_, _| 20)])
+        });
+        assert_eq!(summary.bricks, 1);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+// errors that can occur while reading inputs, generating bricks, or writing saves
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("at least one heightmap image is required")]
+    NoHeightmaps,
+    #[error("could not open image {}: {source}", path.display())]
+    Image {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+    #[error("could not write image {}: {source}", path.display())]
+    WriteImage {
+        path: PathBuf,
+        #[source]
+        source: image::ImageError,
+    },
+    #[error("could not encode screenshot: {0}")]
+    EncodeImage(#[source] image::ImageError),
+    #[error("could not decode image: {0}")]
+    Decode(#[source] image::ImageError),
+    #[error("unsupported {kind} format '{ext}'")]
+    UnsupportedFormat { kind: &'static str, ext: String },
+    #[error("missing {kind} format for '{}'", path.display())]
+    MissingFormat { kind: &'static str, path: PathBuf },
+    #[error("mismatched heightmap sizes: expected {expected:?}, found {found:?}")]
+    MismatchedHeightmaps {
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    #[error("{blends} blends were given for {heightmaps} heightmaps, expected one per heightmap")]
+    MismatchedBlends { blends: usize, heightmaps: usize },
+    #[error(
+        "heightmap and colormap must have same dimensions: heightmap is {heightmap:?}, colormap is {colormap:?}"
+    )]
+    MismatchedColormap {
+        heightmap: (u32, u32),
+        colormap: (u32, u32),
+    },
+    #[error("crop {crop:?} (x, y, width, height) does not fit in the {size:?} maps")]
+    CropOutOfBounds {
+        crop: (u32, u32, u32, u32),
+        size: (u32, u32),
+    },
+    #[error(
+        "heightmap and mask must have same dimensions: heightmap is {heightmap:?}, mask is {mask:?}"
+    )]
+    MismatchedMask {
+        heightmap: (u32, u32),
+        mask: (u32, u32),
+    },
+    #[error("max brick {kind} must be between {min} and {max}, found {value}")]
+    BrickLimit {
+        kind: &'static str,
+        value: u16,
+        min: u16,
+        max: u16,
+    },
+    #[error("Stopped by user")]
+    Stopped,
+    #[error("output file must end with .brz, .brdb, .obj, .stl, .gltf or .glb")]
+    UnsupportedOutput,
+    #[error("brdb saves can only be written to a file")]
+    BrdbStream,
+    #[error("could not read {}: {source}", path.display())]
+    ReadFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("could not write {}: {source}", path.display())]
+    WriteFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("could not read save {}: {source}", path.display())]
+    ReadSave {
+        path: PathBuf,
+        #[source]
+        source: brdb::BrError,
+    },
+    #[error("unknown filter '{0}', expected nearest, bilinear, bicubic, area or lanczos")]
+    UnknownFilter(String),
+    #[error(
+        "unknown collision channel '{0}', expected player, weapon, interact, tool, physics, all or none"
+    )]
+    UnknownChannel(String),
+    #[error("invalid color '{0}', expected a hex color such as #6b4c30")]
+    InvalidColor(String),
+    #[error(
+        "unknown blend '{0}', expected add, max, min, multiply or detail with an optional :weight"
+    )]
+    UnknownBlend(String),
+    #[error(
+        "unknown height decoding '{0}', expected gray, r16, rgb24, rgba32, terrain-rgb or terrarium"
+    )]
+    UnknownDecoding(String),
+    #[error("invalid curve point on line {line} of {}", path.display())]
+    InvalidCurve { path: PathBuf, line: usize },
+    #[error("save has no bricks")]
+    EmptySave,
+    #[error(
+        "the merged save has {components} components, {wires} wires and {entities} entities \
+         that would be lost, they must be dropped explicitly to merge into it"
+    )]
+    UnmergeableSave {
+        components: u32,
+        wires: u32,
+        entities: u32,
+    },
+    #[error("no owner named '{0}' in the merged save")]
+    UnknownOwner(String),
+    #[error("failed to encode save: {0}")]
+    Encode(#[from] brdb::BrError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
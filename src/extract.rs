@@ -0,0 +1,216 @@
+use std::path::Path;
+
+use brdb::Brick;
+use image::{DynamicImage, ImageBuffer, Luma, Rgba, RgbaImage};
+use log::warn;
+
+use crate::{Error, merge::read_world, render::Cuboid, util::to_srgb};
+
+// how heights are stored in an extracted heightmap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeightEncoding {
+    // 8 bit grayscale
+    #[default]
+    Gray8,
+    // 16 bit grayscale
+    Gray16,
+    // 32 bit heights split across the rgba channels, read with --hdmap
+    Rgba,
+}
+
+impl HeightEncoding {
+    fn max(self) -> u32 {
+        match self {
+            HeightEncoding::Gray8 => u8::MAX as u32,
+            HeightEncoding::Gray16 => u16::MAX as u32,
+            HeightEncoding::Rgba => u32::MAX,
+        }
+    }
+}
+
+// settings for turning bricks back into a heightmap and colormap,
+// these match the generator options used to create a save
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    // half the width and length of a pixel in brick units, 5 per stud
+    pub size: (u16, u16),
+    // vertical scale the heights were multiplied by
+    pub scale: u32,
+    pub encoding: HeightEncoding,
+    // write brick colors as linear rgb instead of sRGB
+    pub lrgb: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            size: (5, 5),
+            scale: 1,
+            encoding: HeightEncoding::default(),
+            lrgb: false,
+        }
+    }
+}
+
+// read a save and extract its heightmap and colormap
+pub fn extract_save(
+    path: impl AsRef<Path>,
+    options: &ExtractOptions,
+) -> Result<(DynamicImage, RgbaImage), Error> {
+    // only the bricks are rasterized
+    let world = read_world(path, true)?;
+    if world.bricks.is_empty() {
+        return Err(Error::EmptySave);
+    }
+    Ok(extract_bricks(&world.bricks, options))
+}
+
+// rasterize the top surface of bricks into a heightmap and colormap
+pub fn extract_bricks(bricks: &[Brick], options: &ExtractOptions) -> (DynamicImage, RgbaImage) {
+    let cuboids = bricks.iter().map(Cuboid::from).collect::<Vec<_>>();
+    let cell = [options.size.0, options.size.1].map(|s| s.max(1) as f32 * 2.0);
+
+    // align the grid to the pixels of generated saves, which start at 0
+    let mut min = [f32::MAX; 2];
+    let mut max = [f32::MIN; 2];
+    for c in &cuboids {
+        for a in 0..2 {
+            min[a] = min[a].min(c.center[a] - c.half_size[a]);
+            max[a] = max[a].max(c.center[a] + c.half_size[a]);
+        }
+    }
+    let origin = [0, 1].map(|a| (min[a] / cell[a]).floor() * cell[a]);
+    let width = ((max[0] - origin[0]) / cell[0]).ceil().max(1.0) as u32;
+    let height = ((max[1] - origin[1]) / cell[1]).ceil().max(1.0) as u32;
+
+    // the highest brick top and the index of that brick for every pixel
+    let mut tops = vec![None::<(f32, usize)>; (width * height) as usize];
+    for (i, c) in cuboids.iter().enumerate() {
+        let top = c.center[2] + c.half_size[2];
+        // pixels with centers inside the brick
+        let [x0, y0] = [0, 1]
+            .map(|a| ((c.center[a] - c.half_size[a] - origin[a]) / cell[a] - 0.5).ceil() as u32);
+        let [x1, y1] = [0, 1]
+            .map(|a| ((c.center[a] + c.half_size[a] - origin[a]) / cell[a] - 0.5).ceil() as u32);
+        for y in y0..y1.min(height) {
+            for x in x0..x1.min(width) {
+                let pixel = &mut tops[(y * width + x) as usize];
+                if pixel.is_none_or(|(z, _)| top > z) {
+                    *pixel = Some((top, i));
+                }
+            }
+        }
+    }
+
+    // invert the brick placement in quad.rs, the top of a column is scale * height + 2
+    let mut clamped = false;
+    let heights = tops
+        .iter()
+        .map(|pixel| {
+            let Some((top, _)) = pixel else {
+                return 0;
+            };
+            let h = ((top - 2.0) / options.scale.max(1) as f32).round().max(0.0);
+            if h > options.encoding.max() as f32 {
+                clamped = true;
+            }
+            (h as u32).min(options.encoding.max())
+        })
+        .collect::<Vec<_>>();
+    if clamped {
+        warn!(
+            "Some heights do not fit in the heightmap and were clamped, try a larger vertical scale"
+        );
+    }
+
+    let heightmap = match options.encoding {
+        HeightEncoding::Gray8 => {
+            DynamicImage::ImageLuma8(ImageBuffer::from_fn(width, height, |x, y| {
+                Luma([heights[(y * width + x) as usize] as u8])
+            }))
+        }
+        HeightEncoding::Gray16 => {
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| {
+                Luma([heights[(y * width + x) as usize] as u16])
+            }))
+        }
+        HeightEncoding::Rgba => {
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+                Rgba(heights[(y * width + x) as usize].to_be_bytes())
+            }))
+        }
+    };
+
+    // brick colors are stored as linear rgb
+    let colormap = RgbaImage::from_fn(width, height, |x, y| {
+        let Some((_, i)) = tops[(y * width + x) as usize] else {
+            return Rgba([0, 0, 0, 0]);
+        };
+        let color = &bricks[i].color;
+        let rgba = [color.r, color.g, color.b, 255];
+        Rgba(if options.lrgb { rgba } else { to_srgb(rgba) })
+    });
+
+    (heightmap, colormap)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Rgba};
+
+    use super::*;
+    use crate::{
+        map::{ColormapPNG, Decoding, Heightmap, HeightmapPNG},
+        quad::gen_opt_heightmap,
+        util::GenOptions,
+    };
+
+    // generate bricks from a heightmap, extract them, and read the extracted image back
+    fn round_trip(heightmap: DynamicImage, encoding: HeightEncoding) {
+        let (width, height) = (heightmap.width(), heightmap.height());
+        let original = HeightmapPNG::from_images(vec![heightmap], Decoding::Gray).unwrap();
+        let colormap = ColormapPNG::from_image(
+            RgbaImage::from_pixel(width, height, Rgba([90, 160, 60, 255])),
+            false,
+        );
+        let options = GenOptions::default();
+        let bricks =
+            gen_opt_heightmap(&original, &colormap, None, None, options, |_| true).unwrap();
+
+        let (extracted, colors) = extract_bricks(
+            &bricks,
+            &ExtractOptions {
+                encoding,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            extracted.color().bits_per_pixel(),
+            encoding.max().ilog2() as u16 + 1
+        );
+        // colors pass through 8 bit linear rgb, so they can be one off
+        let color = colors.get_pixel(0, 0).0;
+        assert!((0..4).all(|i| color[i].abs_diff([90, 160, 60, 255][i]) <= 1));
+
+        let extracted = HeightmapPNG::from_images(vec![extracted], Decoding::Gray).unwrap();
+        assert_eq!(extracted.size(), (width, height));
+        for y in 0..height {
+            for x in 0..width {
+                assert_eq!(extracted.at(x, y), original.at(x, y), "pixel {x},{y}");
+            }
+        }
+    }
+
+    #[test]
+    fn gray8_round_trip() {
+        let img = GrayImage::from_fn(12, 9, |x, y| Luma([((x * 37 + y * 11) % 256) as u8]));
+        round_trip(DynamicImage::ImageLuma8(img), HeightEncoding::Gray8);
+    }
+
+    #[test]
+    fn gray16_round_trip() {
+        // heights above 255 only survive if the 16 bits are kept when loading
+        let img = ImageBuffer::from_fn(12, 9, |x, y| Luma([(x * 1013 + y * 257) as u16]));
+        round_trip(DynamicImage::ImageLuma16(img), HeightEncoding::Gray16);
+    }
+}
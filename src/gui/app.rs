@@ -1,22 +1,39 @@
 #![allow(dead_code, unused_variables)]
 use std::{
     borrow::Cow,
-    collections::HashSet,
+    f32::consts::FRAC_PI_2,
+    fs,
+    hash::{Hash, Hasher},
     path::PathBuf,
-    sync::mpsc::{self, Receiver, Sender},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
     thread::{self},
     time::Duration,
 };
 
 use super::logger;
-use crate::{gui::util::maps_from_files, quad::*, util::bricks_to_save, util::*};
+use crate::{
+    blend::{Blend, BlendMode},
+    cache::Fnv64,
+    collision::{Channels, CollisionRules},
+    convert::*,
+    map::{Decoding, Elevation, Source},
+    meta::{DEFAULT_DESCRIPTION, SaveMeta, Screenshot},
+    preview::{PreviewMode, render_tiles},
+    quad::{TileRect, optimize_heightmap},
+    remap::{Curve, Remap},
+    render::{Camera, Cuboid, render_cuboids},
+    resample::{Filter, Resize},
+    smooth::Smooth,
+    strata::Strata,
+    util::*,
+};
 use brdb::assets::bricks::{
     PB_DEFAULT_BRICK, PB_DEFAULT_MICRO_BRICK, PB_DEFAULT_STUDDED, PB_DEFAULT_TILE,
 };
 use eframe::App;
 use egui::{
-    Button, CentralPanel, Color32, Context, Id, ImageSource, ProgressBar, ScrollArea,
-    TopBottomPanel, Ui, vec2,
+    Button, CentralPanel, Color32, ColorImage, Context, Id, ImageSource, ProgressBar, ScrollArea,
+    Sense, TextureHandle, TextureOptions, TopBottomPanel, Ui, vec2,
 };
 use log::{error, info};
 use poll_promise::Promise;
@@ -30,28 +47,102 @@ enum BrickMode {
     Micro,
 }
 
+// which preview image is stored in the save
+#[derive(PartialEq, Clone, Copy)]
+enum ScreenshotMode {
+    Colormap,
+    Render,
+    None,
+}
+
 type Progress = (&'static str, f32);
 
+// optimized tiles and bricks generated for the preview panel
+struct Preview {
+    size: (u32, u32),
+    rects: Vec<TileRect>,
+    cuboids: Vec<Cuboid>,
+}
+
+// what the preview texture was rendered with
+#[derive(PartialEq, Clone, Copy)]
+enum PreviewView {
+    Tiles(PreviewMode),
+    Bricks(Camera, (u32, u32)),
+}
+
 pub struct HeightmapApp {
     // options for the generator
     heightmaps: Vec<PathBuf>,
+    // blend of each heightmap, in the same order
+    blends: Vec<Blend>,
     colormap: Option<PathBuf>,
+    mask: Option<PathBuf>,
+    collision_mask: Option<PathBuf>,
     out_file: String,
     out_clipboard: bool,
+    save_name: String,
+    save_author: String,
+    save_description: String,
+    screenshot: ScreenshotMode,
     vertical_scale: u32,
     horizontal_size: u16,
+    opt_size_y: bool,
+    size_y: u16,
     opt_quad: bool,
     opt_cull: bool,
-    opt_nocollide: bool,
+    collision: Channels,
+    opt_strata: bool,
+    strata: Strata,
+    opt_collision_below: bool,
+    collision_below: u32,
+    collision_below_channels: Channels,
+    opt_collision_above: bool,
+    collision_above: u32,
+    collision_above_channels: Channels,
+    collision_mask_channels: Channels,
     opt_lrgb: bool,
-    opt_hdmap: bool,
+    decoding: Decoding,
+    elevation: Elevation,
     opt_snap: bool,
     opt_glow: bool,
     mode: BrickMode,
+    anchor: Anchor,
+    offset: [i32; 3],
+    opt_base_z: bool,
+    base_z: i32,
+    owner_name: String,
+    owner_id: String,
+    max_brick_size: u16,
+    max_brick_height: u16,
+    smooth: Smooth,
+    opt_normalize: bool,
+    normalize: [u32; 2],
+    opt_clamp: bool,
+    clamp: [u32; 2],
+    gamma: f32,
+    curve: Option<(PathBuf, Curve)>,
+    opt_terrace: bool,
+    terrace: u32,
+    opt_crop: bool,
+    crop: [u32; 4],
+    transform: Transform,
+    opt_resize: bool,
+    resize: (u32, u32),
+    height_filter: Filter,
+    color_filter: Filter,
     progress: Progress,
     progress_channel: (Sender<Progress>, Receiver<Progress>),
     promise: Option<Promise<Result<(), String>>>,
     gen_interrupt: Option<Sender<()>>,
+    preview_mode: PreviewMode,
+    preview_3d: bool,
+    camera: Camera,
+    preview_key: Option<u64>,
+    // dropped to stop the preview that is generating
+    preview_interrupt: Option<Sender<()>>,
+    preview: Option<Promise<Result<Preview, String>>>,
+    preview_texture: Option<(PreviewView, TextureHandle)>,
 }
 
 impl Default for HeightmapApp {
@@ -59,23 +150,73 @@ impl Default for HeightmapApp {
         Self {
             // default generator options
             heightmaps: vec![],
+            blends: vec![],
             colormap: None,
+            mask: None,
+            collision_mask: None,
             out_file: "out.brz".to_string(),
             out_clipboard: true,
+            save_name: String::new(),
+            save_author: String::new(),
+            save_description: String::new(),
+            screenshot: ScreenshotMode::Colormap,
             vertical_scale: 1,
             horizontal_size: 1,
+            opt_size_y: false,
+            size_y: 1,
             opt_quad: true,
             opt_cull: false,
-            opt_nocollide: false,
+            collision: Channels::ALL,
+            opt_strata: false,
+            strata: Strata::default(),
+            opt_collision_below: false,
+            collision_below: 10,
+            collision_below_channels: Channels::NOCOLLIDE,
+            opt_collision_above: false,
+            collision_above: 200,
+            collision_above_channels: Channels::NOCOLLIDE,
+            collision_mask_channels: Channels::NOCOLLIDE,
             opt_lrgb: false,
             opt_snap: false,
             opt_glow: false,
-            opt_hdmap: false,
+            decoding: Decoding::Gray,
+            elevation: Elevation::default(),
             mode: BrickMode::Default,
+            anchor: Anchor::Corner,
+            offset: [0; 3],
+            opt_base_z: false,
+            base_z: 0,
+            owner_name: String::new(),
+            owner_id: String::new(),
+            max_brick_size: 500,
+            max_brick_height: 250,
+            smooth: Smooth::default(),
+            opt_normalize: false,
+            normalize: [0, 255],
+            opt_clamp: false,
+            clamp: [0, 255],
+            gamma: 1.0,
+            curve: None,
+            opt_terrace: false,
+            terrace: 8,
+            opt_crop: false,
+            crop: [0, 0, 256, 256],
+            transform: Transform::default(),
+            opt_resize: false,
+            resize: (512, 512),
+            height_filter: Filter::Bilinear,
+            color_filter: Filter::Area,
             promise: None,
             progress: ("Pending", 0.),
             progress_channel: mpsc::channel(),
             gen_interrupt: None,
+            preview_mode: PreviewMode::Color,
+            preview_3d: false,
+            camera: Camera::default(),
+            preview_key: None,
+            preview_interrupt: None,
+            preview: None,
+            preview_texture: None,
         }
     }
 }
@@ -84,7 +225,14 @@ impl HeightmapApp {
     fn options(&self) -> GenOptions {
         // output options
         let mut options = GenOptions {
-            size: self.horizontal_size * 5,
+            size: (
+                self.horizontal_size * 5,
+                if self.opt_size_y {
+                    self.size_y
+                } else {
+                    self.horizontal_size
+                } * 5,
+            ),
             scale: self.vertical_scale,
             cull: self.opt_cull,
             asset: PB_DEFAULT_BRICK,
@@ -94,16 +242,63 @@ impl HeightmapApp {
             snap: self.opt_snap,
             img: self.heightmaps.is_empty() && self.colormap.is_some(),
             glow: self.opt_glow,
-            hdmap: self.opt_hdmap,
+            decoding: self.decoding,
+            elevation: self.elevation,
+            // plain sums keep the faster path
+            blends: if self.blends.iter().all(|b| *b == Blend::default()) {
+                vec![]
+            } else {
+                self.blends.clone()
+            },
             lrgb: self.opt_lrgb,
-            nocollide: self.opt_nocollide,
             quadtree: self.opt_quad,
+            anchor: self.anchor,
+            offset: (self.offset[0], self.offset[1], self.offset[2]),
+            base_z: self.opt_base_z.then_some(self.base_z),
+            crop: self.opt_crop.then_some(Crop {
+                x: self.crop[0],
+                y: self.crop[1],
+                width: self.crop[2],
+                height: self.crop[3],
+            }),
+            transform: self.transform,
+            resize: self
+                .opt_resize
+                .then_some(Resize::Pixels(self.resize.0, self.resize.1)),
+            height_filter: self.height_filter,
+            color_filter: self.color_filter,
+            max_brick_size: self.max_brick_size,
+            max_brick_height: self.max_brick_height,
+            owner: parse_guid(&self.owner_id).map(|id| {
+                BrickOwner::new(Some(self.owner_name.trim()).filter(|n| !n.is_empty()), id)
+            }),
+            collision: CollisionRules {
+                channels: self.collision,
+                below: self
+                    .opt_collision_below
+                    .then_some((self.collision_below, self.collision_below_channels)),
+                above: self
+                    .opt_collision_above
+                    .then_some((self.collision_above, self.collision_above_channels)),
+                mask: self.collision_mask_channels,
+            },
+            strata: self.opt_strata.then_some(self.strata),
+            smooth: self.smooth,
+            remap: Remap {
+                clamp: self.opt_clamp.then_some((self.clamp[0], self.clamp[1])),
+                normalize: self
+                    .opt_normalize
+                    .then_some((self.normalize[0], self.normalize[1])),
+                gamma: self.gamma,
+                curve: self.curve.as_ref().map(|(_, curve)| curve.clone()),
+                terrace: self.opt_terrace.then_some(self.terrace),
+            },
         };
 
         if options.tile {
             options.asset = PB_DEFAULT_TILE;
         } else if options.micro {
-            options.size /= 5;
+            options.size = (options.size.0 / 5, options.size.1 / 5);
             options.asset = PB_DEFAULT_MICRO_BRICK;
         }
         if options.stud {
@@ -119,6 +314,19 @@ impl HeightmapApp {
         let options = self.options();
         let heightmap_files = self.heightmaps.clone();
         let colormap_file = self.colormap.clone();
+        let mask_file = self.mask.clone();
+        let collision_mask_file = self.collision_mask.clone();
+        let text = |s: &str| Some(s.trim().to_string()).filter(|s| !s.is_empty());
+        let meta = SaveMeta {
+            name: text(&self.save_name),
+            description: text(&self.save_description),
+            author: text(&self.save_author),
+            screenshot: Some(match self.screenshot {
+                ScreenshotMode::Colormap => Screenshot::Colormap,
+                ScreenshotMode::Render => Screenshot::Render,
+                ScreenshotMode::None => Screenshot::None,
+            }),
+        };
 
         let progress_tx = self.progress_channel.0.clone();
         let progress = move |status, p| progress_tx.send((status, p)).unwrap();
@@ -135,68 +343,25 @@ impl HeightmapApp {
             progress("Reading", 0.);
 
             thread::spawn(move || {
-                macro_rules! stop_if_stopped {
-                    () => {
-                        if is_stopped() {
-                            sender.send(Err("Stopped by user".to_string()));
-                            return;
-                        }
-                    };
-                }
-
-                info!("Reading image files...");
-                let (heightmap, colormap) =
-                    match maps_from_files(&options, heightmap_files, colormap_file) {
-                        Ok(hc) => hc,
-                        Err(err) => {
-                            error!("{err}");
-                            return sender.send(Err(err));
-                        }
-                    };
-
-                stop_if_stopped!();
-                progress("Generating", 0.10);
-
-                let bricks = match gen_opt_heightmap(&*heightmap, &*colormap, options, |p| {
-                    progress("Generating", 0.1 + 0.85 * p);
-                    !is_stopped()
-                }) {
-                    Ok(b) => b,
+                let converter = match Output::from_path(&out_file) {
+                    Ok(output) => Converter::new(options, output)
+                        .heightmaps(heightmap_files)
+                        .colormap(colormap_file)
+                        .mask(mask_file)
+                        .collision_mask(collision_mask_file)
+                        .meta(meta),
                     Err(err) => {
                         error!("{err}");
-                        return sender.send(Err(err));
+                        return sender.send(Err(err.to_string()));
                     }
                 };
-                stop_if_stopped!();
-
-                info!("Writing Save to {}", out_file);
-                progress("Writing", 0.95);
-                let data = bricks_to_save(bricks);
-
-                if out_file.to_lowercase().ends_with(".brz") {
-                    let brz = match data.to_brz_vec() {
-                        Ok(b) => b,
-                        Err(e) => {
-                            let err = format!("failed to encode brz: {e}");
-                            error!("{err}");
-                            return sender.send(Err(err));
-                        }
-                    };
-                    if let Err(e) = std::fs::write(&out_file, brz) {
-                        let err = format!("failed to write file: {e}");
-                        error!("{err}");
-                        return sender.send(Err(err));
-                    }
-                } else if out_file.to_lowercase().ends_with(".brdb") {
-                    if let Err(e) = data.write_brdb(&out_file) {
-                        let err = format!("failed to write file: {e}");
-                        error!("{err}");
-                        return sender.send(Err(err));
-                    };
-                } else {
-                    let err = "output file must end with .brz or .brdb".to_string();
+
+                if let Err(err) = converter.run(|status, p| {
+                    progress(status, p);
+                    !is_stopped()
+                }) {
                     error!("{err}");
-                    return sender.send(Err(err));
+                    return sender.send(Err(err.to_string()));
                 }
 
                 if is_clipboard {
@@ -208,7 +373,9 @@ impl HeightmapApp {
                         .to_string();
 
                     // lowercase the first letter
-                    full_path.get_mut(0..1).map(|s| s.make_ascii_lowercase());
+                    if let Some(s) = full_path.get_mut(0..1) {
+                        s.make_ascii_lowercase();
+                    }
 
                     if let Err(e) = clipboard_win::raw::open() {
                         error!("failed to open clipboard: {e}");
@@ -228,9 +395,6 @@ impl HeightmapApp {
                     }
                 }
 
-                stop_if_stopped!();
-                progress("Finished", 1.0);
-
                 info!("Done!");
                 sender.send(Ok(()));
                 thread::sleep(Duration::from_millis(500));
@@ -241,6 +405,70 @@ impl HeightmapApp {
         });
     }
 
+    // identify the inputs and options used for a preview, files are identified by
+    // their modification time and size so edited images are previewed again
+    fn preview_key(&self) -> u64 {
+        let mut hasher = Fnv64::default();
+        self.options().hash(&mut hasher);
+        self.heightmaps.len().hash(&mut hasher);
+        for path in self
+            .heightmaps
+            .iter()
+            .map(Some)
+            .chain([&self.colormap, &self.mask, &self.collision_mask].map(Option::as_ref))
+        {
+            path.hash(&mut hasher);
+            path.and_then(|path| fs::metadata(path).ok())
+                .map(|meta| (meta.modified().ok(), meta.len()))
+                .hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    fn run_preview(&mut self) {
+        let options = self.options();
+        let heightmaps = self
+            .heightmaps
+            .iter()
+            .cloned()
+            .map(Source::from)
+            .collect::<Vec<_>>();
+        let colormap = self.colormap.clone().map(Source::from);
+        let mask = self.mask.clone().map(Source::from);
+        let collision_mask = self.collision_mask.clone().map(Source::from);
+
+        // replacing the sender stops the previous preview
+        let (tx, rx) = mpsc::channel::<()>();
+        self.preview_interrupt = Some(tx);
+        let is_stopped = move || !matches!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        self.preview = Some(Promise::spawn_thread("preview", move || {
+            let maps = maps_from_sources(
+                &options,
+                &heightmaps,
+                colormap.as_ref(),
+                mask.as_ref(),
+                collision_mask.as_ref(),
+            )
+            .map_err(|e| e.to_string())?;
+            let quad = optimize_heightmap(
+                &*maps.heightmap,
+                &*maps.colormap,
+                maps.mask.as_deref(),
+                maps.collision_mask.as_deref(),
+                &options,
+                |_| !is_stopped(),
+            )
+            .map_err(|e| e.to_string())?;
+            let rects = quad.rects(&options).collect();
+            Ok(Preview {
+                size: quad.size(),
+                rects,
+                cuboids: quad.into_bricks(options).iter().map(Cuboid::from).collect(),
+            })
+        }));
+    }
+
     fn draw_header(&self, ui: &mut Ui) {
         ui.horizontal(|ui| {
             ui.heading("heightmap2brz");
@@ -269,23 +497,152 @@ impl HeightmapApp {
                     ui.add(egui::TextEdit::singleline(&mut self.out_file).hint_text("File Name"));
                 });
                 ui.end_row();
-                let out_file_lowercase = self.out_file.to_lowercase();
-                let is_brz = out_file_lowercase.ends_with(".brz");
-                if !is_brz && !out_file_lowercase.ends_with(".brdb") {
+                if let Err(err) = Output::from_path(&self.out_file) {
                     ui.label("Warning:");
-                    ui.colored_label(Color32::RED, "Output file must end with .brz or .brdb");
+                    ui.colored_label(Color32::RED, err.to_string());
                     ui.end_row();
                 }
 
+                ui.label("Save Info")
+                    .on_hover_text("Shown for the save in the game's save browser");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.save_name)
+                            .hint_text("Name")
+                            .desired_width(160.0),
+                    );
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.save_author)
+                            .hint_text("Author")
+                            .desired_width(120.0),
+                    );
+                });
+                ui.end_row();
+                ui.label("Description")
+                    .on_hover_text("The generator options are added after the description");
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.save_description)
+                        .hint_text(DEFAULT_DESCRIPTION)
+                        .desired_rows(2),
+                );
+                ui.end_row();
+                ui.label("Screenshot")
+                    .on_hover_text("Preview image stored in the save");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.screenshot, ScreenshotMode::Colormap, "Colormap")
+                        .on_hover_text("Use the colormap scaled down");
+                    ui.radio_value(&mut self.screenshot, ScreenshotMode::Render, "Render")
+                        .on_hover_text("Render the bricks from above");
+                    ui.radio_value(&mut self.screenshot, ScreenshotMode::None, "None")
+                        .on_hover_text("Do not store a preview image");
+                });
+                ui.end_row();
+
                 ui.label("Horizontal Scale")
                     .on_hover_text("The size of each pixel in studs (or microbricks)");
                 ui.add(egui::Slider::new(&mut self.horizontal_size, 1..=100).text("studs"));
                 ui.end_row();
+                ui.label("Horizontal Scale Y").on_hover_text(
+                    "A different pixel size along Y for maps with pixels that are not square",
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.opt_size_y, "");
+                    ui.add_enabled(
+                        self.opt_size_y,
+                        egui::Slider::new(&mut self.size_y, 1..=100).text("studs"),
+                    );
+                });
+                ui.end_row();
                 ui.label("Vertical Size")
                     .on_hover_text("The height of each shade of grey from the heightmap");
                 ui.add(egui::Slider::new(&mut self.vertical_scale, 1..=100).text("units"));
                 ui.end_row();
 
+                ui.label("Smoothing")
+                    .on_hover_text("Remove noise from the heights so more bricks can merge");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.smooth.despeckle, "Despeckle")
+                        .on_hover_text("Flatten single pixel spikes and pits");
+                    let mut median = self.smooth.median.is_some();
+                    ui.checkbox(&mut median, "Median")
+                        .on_hover_text("Replace heights with the median of the pixels around them");
+                    let mut radius = self.smooth.median.unwrap_or(1);
+                    ui.add_enabled(median, egui::DragValue::new(&mut radius).range(1..=10));
+                    self.smooth.median = median.then_some(radius);
+                    let mut gaussian = self.smooth.gaussian.is_some();
+                    ui.checkbox(&mut gaussian, "Gaussian")
+                        .on_hover_text("Blur the heights with this standard deviation in pixels");
+                    let mut sigma = self.smooth.gaussian.unwrap_or(1.0);
+                    ui.add_enabled(
+                        gaussian,
+                        egui::DragValue::new(&mut sigma)
+                            .speed(0.05)
+                            .range(0.1..=20.0),
+                    );
+                    self.smooth.gaussian = gaussian.then_some(sigma);
+                });
+                ui.end_row();
+
+                ui.label("Height Range")
+                    .on_hover_text("Change the range of heights before they are scaled");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.opt_clamp, "Clamp")
+                        .on_hover_text("Clamp heights to this range");
+                    for v in &mut self.clamp {
+                        ui.add_enabled(self.opt_clamp, egui::DragValue::new(v));
+                    }
+                    ui.checkbox(&mut self.opt_normalize, "Normalize")
+                        .on_hover_text("Stretch the lowest and highest heights to this range");
+                    for v in &mut self.normalize {
+                        ui.add_enabled(self.opt_normalize, egui::DragValue::new(v));
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Height Curve")
+                    .on_hover_text("Reshape the heights between the lowest and highest");
+                ui.horizontal(|ui| {
+                    ui.label("Gamma")
+                        .on_hover_text("Above 1 flattens lowlands and exaggerates peaks");
+                    ui.add(
+                        egui::DragValue::new(&mut self.gamma)
+                            .speed(0.02)
+                            .range(0.05..=10.0),
+                    );
+                    ui.checkbox(&mut self.opt_terrace, "Terrace")
+                        .on_hover_text("Round heights into this many steps");
+                    ui.add_enabled(
+                        self.opt_terrace,
+                        egui::DragValue::new(&mut self.terrace).range(1..=1000),
+                    );
+                    if ui
+                        .button("Curve")
+                        .on_hover_text(
+                            "Load a piecewise-linear curve with an input,output point per line",
+                        )
+                        .clicked()
+                    {
+                        match native_dialog::DialogBuilder::file()
+                            .open_single_file()
+                            .show()
+                        {
+                            Ok(Some(path)) => match Curve::from_file(&path) {
+                                Ok(curve) => self.curve = Some((path, curve)),
+                                Err(e) => error!("Error loading curve: {e}"),
+                            },
+                            Ok(None) => {}
+                            Err(e) => error!("Error selecting curve file: {e}"),
+                        }
+                    }
+                    if let Some((path, _)) = &self.curve {
+                        ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                        if ui.button("✖").clicked() {
+                            self.curve = None;
+                        }
+                    }
+                });
+                ui.end_row();
+
                 ui.label("Options")
                     .on_hover_text("A list of options for modifying how the generator works");
                 ui.horizontal(|ui| {
@@ -295,12 +652,8 @@ impl HeightmapApp {
                         "Automatically remove bottom level bricks and fully transparent bricks\n\
                             In image mode, only transparent bricks are removed",
                     );
-                    ui.checkbox(&mut self.opt_nocollide, "No Collide")
-                        .on_hover_text("Disable brick collision");
                     ui.checkbox(&mut self.opt_lrgb, "LRGB")
                         .on_hover_text("Use linear rgb input color instead of sRGB");
-                    ui.checkbox(&mut self.opt_hdmap, "HD Map")
-                        .on_hover_text("Using a high detail rgb color encoded heightmap");
                     ui.checkbox(&mut self.opt_glow, "Glow")
                         .on_hover_text("Glow bricks at lowest intensity");
                     ui.checkbox(&mut self.opt_quad, "Quadtree").on_hover_text(
@@ -309,6 +662,212 @@ impl HeightmapApp {
                 });
                 ui.end_row();
 
+                ui.label("Crop")
+                    .on_hover_text("Only generate part of the maps");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.opt_crop, "Enabled")
+                        .on_hover_text("Keep a region of the maps, in pixels");
+                    for (v, label) in self.crop.iter_mut().zip(["X", "Y", "W", "H"]) {
+                        ui.label(label);
+                        ui.add_enabled(self.opt_crop, egui::DragValue::new(v));
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Transform")
+                    .on_hover_text("Rotate and mirror the maps before generating");
+                ui.horizontal(|ui| {
+                    for (turns, label) in [(0, "0°"), (1, "90°"), (2, "180°"), (3, "270°")] {
+                        ui.radio_value(&mut self.transform.rotate, turns, label)
+                            .on_hover_text("Rotate the maps clockwise");
+                    }
+                    ui.checkbox(&mut self.transform.flip_x, "Flip X")
+                        .on_hover_text("Mirror the maps horizontally");
+                    ui.checkbox(&mut self.transform.flip_y, "Flip Y")
+                        .on_hover_text("Mirror the maps vertically");
+                });
+                ui.end_row();
+
+                ui.label("Resample")
+                    .on_hover_text("Resize the maps before generating");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.opt_resize, "Resize")
+                        .on_hover_text("Resample the maps to this many pixels");
+                    ui.add_enabled(
+                        self.opt_resize,
+                        egui::DragValue::new(&mut self.resize.0).range(1..=16384),
+                    );
+                    ui.label("x");
+                    ui.add_enabled(
+                        self.opt_resize,
+                        egui::DragValue::new(&mut self.resize.1).range(1..=16384),
+                    );
+                    for (label, filter) in [
+                        ("Height", &mut self.height_filter),
+                        ("Color", &mut self.color_filter),
+                    ] {
+                        egui::ComboBox::from_label(label)
+                            .selected_text(format!("{filter:?}"))
+                            .show_ui(ui, |ui| {
+                                for f in [
+                                    Filter::Nearest,
+                                    Filter::Bilinear,
+                                    Filter::Bicubic,
+                                    Filter::Area,
+                                    Filter::Lanczos,
+                                ] {
+                                    ui.selectable_value(filter, f, format!("{f:?}"));
+                                }
+                            });
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Placement")
+                    .on_hover_text("Where the bricks are placed in the world");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.anchor, Anchor::Corner, "Corner")
+                        .on_hover_text("Place the top left corner of the map at the origin");
+                    ui.radio_value(&mut self.anchor, Anchor::Center, "Center")
+                        .on_hover_text("Place the center of the map at the origin");
+                    ui.label("Offset")
+                        .on_hover_text("Move the bricks by x,y,z units");
+                    for v in &mut self.offset {
+                        ui.add(egui::DragValue::new(v));
+                    }
+                    ui.checkbox(&mut self.opt_base_z, "Base Z")
+                        .on_hover_text("Z in units of the bottom of the lowest brick");
+                    ui.add_enabled(self.opt_base_z, egui::DragValue::new(&mut self.base_z));
+                });
+                ui.end_row();
+
+                ui.label("Owner")
+                    .on_hover_text("Give the bricks to a player instead of making them public");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.owner_name)
+                            .hint_text("Name")
+                            .desired_width(120.0),
+                    );
+                    let valid = self.owner_ok();
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.owner_id)
+                            .hint_text("UUID")
+                            .desired_width(260.0)
+                            .text_color_opt((!valid).then_some(Color32::RED)),
+                    )
+                    .on_hover_text("Player id, required to give the bricks to a player");
+                });
+                ui.end_row();
+
+                ui.label("Height Decoding")
+                    .on_hover_text("How heights are stored in the heightmap pixels");
+                egui::ComboBox::from_id_salt("decoding")
+                    .selected_text(self.decoding.name())
+                    .show_ui(ui, |ui| {
+                        for decoding in Decoding::ALL {
+                            ui.selectable_value(&mut self.decoding, decoding, decoding.name())
+                                .on_hover_text(match decoding {
+                                    Decoding::Gray => "8 bit grayscale",
+                                    Decoding::R16 => "16 bits in the red and green channels",
+                                    Decoding::Rgb24 => {
+                                        "24 bits in the red, green and blue channels"
+                                    }
+                                    Decoding::Rgba32 => "32 bits in all four channels (HD map)",
+                                    Decoding::TerrainRgb => "Mapbox Terrain-RGB tiles, in meters",
+                                    Decoding::Terrarium => "Terrarium tiles, in meters",
+                                });
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Elevation").on_hover_text(
+                    "How meters from Terrain-RGB and Terrarium tiles become heights",
+                );
+                ui.add_enabled_ui(self.decoding.is_elevation(), |ui| {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.elevation.scale)
+                                .speed(0.1)
+                                .range(0.01..=1000.0)
+                                .suffix(" per m"),
+                        )
+                        .on_hover_text("Heights per meter, 10 keeps decimeter detail");
+                        ui.add(
+                            egui::DragValue::new(&mut self.elevation.sea_level)
+                                .speed(1.0)
+                                .prefix("sea level +")
+                                .suffix(" m"),
+                        )
+                        .on_hover_text(
+                            "Meters added to every elevation to keep ground below sea level",
+                        );
+                    });
+                });
+                ui.end_row();
+
+                ui.label("Strata").on_hover_text(
+                    "Color the ground under the surface of tall columns so cliffs look like rock",
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.opt_strata, "");
+                    ui.add_enabled_ui(self.opt_strata, |ui| {
+                        ui.label("Surface")
+                            .on_hover_text("Depth in units of the layer colored by the colormap");
+                        ui.add(egui::DragValue::new(&mut self.strata.surface));
+                        ui.label("Subsoil").on_hover_text(
+                            "Depth in units and color of the layer under the surface",
+                        );
+                        ui.add(egui::DragValue::new(&mut self.strata.subsoil));
+                        ui.color_edit_button_srgb(&mut self.strata.subsoil_color);
+                        ui.label("Rock")
+                            .on_hover_text("Color of everything under the subsoil");
+                        ui.color_edit_button_srgb(&mut self.strata.rock_color);
+                    });
+                });
+                ui.end_row();
+
+                ui.label("Collision")
+                    .on_hover_text("Which collision channels the bricks have");
+                ui.horizontal(|ui| Self::channels(ui, &mut self.collision));
+                ui.end_row();
+
+                ui.label("Collision Below").on_hover_text(
+                    "Collision of heights lower than a level, such as water that can be walked through",
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.opt_collision_below, "");
+                    ui.add_enabled_ui(self.opt_collision_below, |ui| {
+                        ui.add(egui::DragValue::new(&mut self.collision_below));
+                        Self::channels(ui, &mut self.collision_below_channels);
+                    });
+                });
+                ui.end_row();
+
+                ui.label("Collision Above").on_hover_text(
+                    "Collision of heights higher than a level, such as decorative peaks",
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.opt_collision_above, "");
+                    ui.add_enabled_ui(self.opt_collision_above, |ui| {
+                        ui.add(egui::DragValue::new(&mut self.collision_above));
+                        Self::channels(ui, &mut self.collision_above_channels);
+                    });
+                });
+                ui.end_row();
+
+                ui.label("Brick Limits")
+                    .on_hover_text("Smaller bricks are easier to edit in game");
+                ui.horizontal(|ui| {
+                    ui.label("Max Size")
+                        .on_hover_text("Largest brick size along x and y, 5 per stud");
+                    ui.add(egui::DragValue::new(&mut self.max_brick_size).range(1..=500));
+                    ui.label("Max Height")
+                        .on_hover_text("Largest height of a single brick in a column");
+                    ui.add(egui::DragValue::new(&mut self.max_brick_height).range(2..=500));
+                });
+                ui.end_row();
+
                 ui.label("Brick Type")
                     .on_hover_text("Change which brick type is used for the save file");
                 ui.horizontal(|ui| {
@@ -339,6 +898,7 @@ impl HeightmapApp {
 
             match result {
                 Ok(files) => {
+                    self.blends = vec![Blend::default(); files.len()];
                     self.heightmaps = files;
                     info!("Selected heightmap files: {:?}", &self.heightmaps);
                 }
@@ -353,16 +913,49 @@ impl HeightmapApp {
             .spacing([8.0, 4.0])
             .min_col_width(4.0)
             .show(ui, |ui| {
-                let mut to_remove = HashSet::new();
-                for img in self.heightmaps.clone() {
+                let mut to_remove = None;
+                for (i, img) in self.heightmaps.clone().iter().enumerate() {
                     if ui.add(Button::new("✖")).clicked() {
-                        to_remove.insert(img.clone());
+                        to_remove = Some(i);
                     }
-                    self.thumb(ui, &img);
-                    ui.label(Path::new(&img).file_name().unwrap().to_str().unwrap());
+                    self.thumb(ui, img);
+                    ui.label(Path::new(img).file_name().unwrap().to_str().unwrap());
+
+                    // the first heightmap is the base the others are blended onto
+                    let blend = &mut self.blends[i];
+                    ui.horizontal(|ui| {
+                        if i > 0 {
+                            egui::ComboBox::from_id_salt(("blend", i))
+                                .selected_text(blend.mode.name())
+                                .show_ui(ui, |ui| {
+                                    for mode in BlendMode::ALL {
+                                        if ui
+                                            .selectable_label(blend.mode == mode, mode.name())
+                                            .clicked()
+                                            && blend.mode != mode
+                                        {
+                                            *blend = Blend::new(mode);
+                                        }
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "How this heightmap is combined with the ones above",
+                                );
+                        }
+                        ui.add(
+                            egui::DragValue::new(&mut blend.weight)
+                                .speed(0.01)
+                                .prefix("×"),
+                        )
+                        .on_hover_text("Weight of this heightmap");
+                    });
                     ui.end_row();
                 }
-                self.heightmaps.retain(|i| !to_remove.contains(i));
+                if let Some(i) = to_remove {
+                    self.heightmaps.remove(i);
+                    self.blends.remove(i);
+                }
             });
 
         ui.separator();
@@ -404,6 +997,191 @@ impl HeightmapApp {
                     ui.label(Path::new(&path).file_name().unwrap().to_str().unwrap());
                 });
         }
+
+        ui.separator();
+
+        ui.heading("Mask Image");
+        ui.label("Select an image where black pixels are left out of the save.");
+
+        // handle mask single file selection
+        if ui
+            .add(Button::new("Select mask").fill(Color32::from_rgb(60, 60, 120)))
+            .clicked()
+        {
+            let result = native_dialog::DialogBuilder::file()
+                .add_filter("PNG Image", ["png"])
+                .open_single_file()
+                .show();
+
+            match result {
+                Ok(file_path) => {
+                    info!("Selected mask file: {:?}", file_path);
+                    self.mask = file_path;
+                }
+                Err(e) => {
+                    error!("Error selecting mask file: {e}");
+                }
+            }
+        }
+
+        if let Some(path) = self.mask.clone() {
+            egui::Grid::new("mask_grid")
+                .striped(true)
+                .spacing([8.0, 4.0])
+                .min_col_width(4.0)
+                .show(ui, |ui| {
+                    if ui.button("✖").clicked() {
+                        self.mask = None;
+                    }
+                    self.thumb(ui, &path);
+                    ui.label(Path::new(&path).file_name().unwrap().to_str().unwrap());
+                });
+        }
+
+        ui.separator();
+
+        ui.heading("Collision Mask");
+        ui.label("Select an image where bricks on white pixels get their own collision.");
+        ui.horizontal(|ui| {
+            ui.label("White pixels");
+            Self::channels(ui, &mut self.collision_mask_channels);
+        });
+
+        // handle collision mask single file selection
+        if ui
+            .add(Button::new("Select collision mask").fill(Color32::from_rgb(60, 60, 120)))
+            .clicked()
+        {
+            let result = native_dialog::DialogBuilder::file()
+                .add_filter("PNG Image", ["png"])
+                .open_single_file()
+                .show();
+
+            match result {
+                Ok(file_path) => {
+                    info!("Selected collision mask file: {:?}", file_path);
+                    self.collision_mask = file_path;
+                }
+                Err(e) => {
+                    error!("Error selecting collision mask file: {e}");
+                }
+            }
+        }
+
+        if let Some(path) = self.collision_mask.clone() {
+            egui::Grid::new("collision_mask_grid")
+                .striped(true)
+                .spacing([8.0, 4.0])
+                .min_col_width(4.0)
+                .show(ui, |ui| {
+                    if ui.button("✖").clicked() {
+                        self.collision_mask = None;
+                    }
+                    self.thumb(ui, &path);
+                    ui.label(Path::new(&path).file_name().unwrap().to_str().unwrap());
+                });
+        }
+    }
+
+    fn draw_preview(&mut self, ctx: &Context, ui: &mut Ui) {
+        ui.heading("Preview");
+
+        if self.heightmaps.is_empty() && self.colormap.is_none() {
+            ui.label("Select some image files to preview the generated bricks.");
+            return;
+        }
+
+        // regenerate the preview when the inputs change, stopping the last one
+        let key = self.preview_key();
+        if self.preview_key != Some(key) {
+            self.preview_key = Some(key);
+            self.preview_texture = None;
+            self.run_preview();
+        }
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.preview_3d, "3D")
+                .on_hover_text("Show the bricks in 3D, drag the preview to orbit");
+            if self.preview_3d {
+                ui.add(
+                    egui::Slider::new(&mut self.camera.zoom, 0.5..=8.0)
+                        .logarithmic(true)
+                        .text("zoom"),
+                );
+            } else {
+                ui.radio_value(&mut self.preview_mode, PreviewMode::Color, "Color")
+                    .on_hover_text("Show the brick colors");
+                ui.radio_value(&mut self.preview_mode, PreviewMode::Height, "Height")
+                    .on_hover_text("Shade bricks by height");
+                ui.radio_value(&mut self.preview_mode, PreviewMode::Random, "Bricks")
+                    .on_hover_text(
+                        "Give each brick a random color to show how well bricks were merged",
+                    );
+            }
+        });
+
+        match self.preview.as_ref().and_then(Promise::ready) {
+            None => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Generating preview...");
+                });
+            }
+            Some(Err(e)) => {
+                ui.colored_label(Color32::RED, format!("Error: {e}"));
+            }
+            Some(Ok(preview)) => {
+                ui.label(format!("{} bricks", preview.cuboids.len()));
+
+                let width = ui.available_width().min(512.0);
+                let view = if self.preview_3d {
+                    PreviewView::Bricks(self.camera, (width as u32, (width * 0.75) as u32))
+                } else {
+                    PreviewView::Tiles(self.preview_mode)
+                };
+
+                // render the preview into a texture when the view changes
+                if self
+                    .preview_texture
+                    .as_ref()
+                    .is_none_or(|(texture_view, _)| *texture_view != view)
+                {
+                    let img = match view {
+                        PreviewView::Tiles(mode) => {
+                            render_tiles(&preview.rects, preview.size, mode, 1024)
+                        }
+                        PreviewView::Bricks(camera, size) => {
+                            render_cuboids(&preview.cuboids, &camera, size)
+                        }
+                    };
+                    let img = ColorImage::from_rgba_unmultiplied(
+                        [img.width() as usize, img.height() as usize],
+                        img.as_raw(),
+                    );
+                    self.preview_texture = Some((
+                        view,
+                        ctx.load_texture("preview", img, TextureOptions::NEAREST),
+                    ));
+                }
+
+                if let Some((_, texture)) = &self.preview_texture {
+                    let [w, h] = texture.size().map(|s| s as f32);
+                    let response = ui.add(
+                        egui::Image::from_texture(texture)
+                            .fit_to_exact_size(vec2(width, width * h / w))
+                            .sense(Sense::drag()),
+                    );
+
+                    // orbit the camera by dragging the preview
+                    if self.preview_3d && response.dragged() {
+                        let delta = response.drag_delta();
+                        self.camera.yaw -= delta.x * 0.01;
+                        self.camera.pitch =
+                            (self.camera.pitch + delta.y * 0.01).clamp(0.05, FRAC_PI_2);
+                    }
+                }
+            }
+        }
     }
 
     fn draw_progress(&mut self, ctx: &Context, ui: &mut Ui) -> bool {
@@ -477,7 +1255,8 @@ impl HeightmapApp {
 
         if heightmap_ok || colormap_ok {
             if ui
-                .add(
+                .add_enabled(
+                    self.owner_ok(),
                     Button::new(match (heightmap_ok, colormap_ok) {
                         (true, true) => "Generate save",
                         (true, false) => "Generate colorless save",
@@ -486,6 +1265,7 @@ impl HeightmapApp {
                     })
                     .fill(Color32::from_rgb(50, 90, 50)),
                 )
+                .on_disabled_hover_text("The owner needs the player's UUID")
                 .clicked()
             {
                 self.run_converter();
@@ -495,7 +1275,29 @@ impl HeightmapApp {
         }
     }
 
-    fn thumb(&mut self, ui: &mut Ui, image: &PathBuf) {
+    // ownership is matched by id, so a name needs an id and the id must be a valid UUID
+    fn owner_ok(&self) -> bool {
+        match self.owner_id.trim() {
+            "" => self.owner_name.trim().is_empty(),
+            id => parse_guid(id).is_some(),
+        }
+    }
+
+    // a checkbox for each collision channel
+    fn channels(ui: &mut Ui, channels: &mut Channels) {
+        ui.checkbox(&mut channels.player, "Player")
+            .on_hover_text("Players walk on the bricks");
+        ui.checkbox(&mut channels.weapon, "Weapon")
+            .on_hover_text("Projectiles hit the bricks");
+        ui.checkbox(&mut channels.interact, "Interact")
+            .on_hover_text("The bricks can be clicked and used");
+        ui.checkbox(&mut channels.tool, "Tool")
+            .on_hover_text("The bricks can be selected with building tools");
+        ui.checkbox(&mut channels.physics, "Physics")
+            .on_hover_text("Physics objects bounce off the bricks");
+    }
+
+    fn thumb(&mut self, ui: &mut Ui, image: &Path) {
         ui.add(
             egui::Image::new(ImageSource::Uri(Cow::from(format!(
                 "file://{}",
@@ -515,6 +1317,8 @@ impl App for HeightmapApp {
                 ui.separator();
                 self.draw_settings(ui);
                 ui.separator();
+                self.draw_preview(ctx, ui);
+                ui.separator();
                 if !self.draw_progress(ctx, ui) {
                     self.draw_submit(ui);
                 }
@@ -1,4 +1,3 @@
 mod app;
 pub mod logger;
 pub use app::*;
-pub mod util;
@@ -1,6 +1,23 @@
+pub mod blend;
+pub mod cache;
+pub mod collision;
+pub mod convert;
+mod error;
+pub mod extract;
 pub mod map;
+pub mod merge;
+pub mod mesh;
+pub mod meta;
+pub mod preview;
 pub mod quad;
+pub mod remap;
+pub mod render;
+pub mod resample;
+pub mod smooth;
+pub mod strata;
 pub mod util;
 
+pub use error::Error;
+
 #[cfg(feature = "gui")]
 pub mod gui;
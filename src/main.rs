@@ -1,15 +1,27 @@
-pub mod map;
-pub mod quad;
-pub mod util;
-
-use crate::{map::*, quad::*, util::*};
 use brdb::assets::bricks::{
     PB_DEFAULT_BRICK, PB_DEFAULT_MICRO_BRICK, PB_DEFAULT_STUDDED, PB_DEFAULT_TILE,
 };
 use clap::clap_app;
 use env_logger::Builder;
+use heightmap::{
+    collision::{Channels, CollisionRules},
+    convert::*,
+    extract::*,
+    map::{Decoding, Elevation, Source},
+    merge::Merge,
+    meta::{SaveMeta, Screenshot},
+    remap::{Curve, Remap},
+    render::Camera,
+    resample::{Filter, Resize},
+    smooth::Smooth,
+    strata::{Strata, parse_color},
+    util::*,
+};
 use log::{LevelFilter, error, info};
-use std::{boxed::Box, io::Write, path::PathBuf};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
 
 fn main() {
     Builder::new()
@@ -21,11 +33,13 @@ fn main() {
         (version: env!("CARGO_PKG_VERSION"))
         (author: "github.com/Meshiest")
         (about: "Converts heightmap png files to Brickadia save files")
-        (@arg INPUT: +required +multiple "Input heightmap PNG images")
-        (@arg output: -o --output +takes_value "Output file (BRDB, BRZ)")
-        (@arg colormap: -c --colormap +takes_value "Input colormap PNG image")
+        (@arg INPUT: +required +multiple "Input heightmap PNG images, - for stdin, or a save (BRZ, BRDB) to convert back to images")
+        (@arg output: -o --output +takes_value "Output file (BRDB, BRZ, OBJ, STL, GLTF, GLB), - for stdout")
+        (@arg format: --format +takes_value possible_value[brz brdb obj stl gltf glb] "Output format, overrides the output file extension")
+        (@arg colormap: -c --colormap +takes_value "Input colormap PNG image, - for stdin, or the colormap to write when converting a save")
+        (@arg mask: --mask +takes_value "Input mask PNG image, black pixels are not generated")
         (@arg vertical: -v --vertical +takes_value "Vertical scale multiplier (default 1)")
-        (@arg size: -s --size +takes_value "Brick stud size (default 1)")
+        (@arg size: -s --size +takes_value "Brick stud size, or x,y for pixels that are not square (default 1)")
         (@arg cull: --cull "Automatically remove bottom level bricks and fully transparent bricks")
         (@arg tile: --tile "Render bricks as tiles")
         (@arg micro: --micro "Render bricks as micro bricks")
@@ -34,34 +48,107 @@ fn main() {
         (@arg lrgb: --lrgb "Use linear rgb input color instead of sRGB")
         (@arg img: -i --img "Make the heightmap flat and render an image")
         (@arg glow: --glow "Make the heightmap glow at 0 intensity")
-        (@arg hdmap: --hdmap "Using a high detail rgb color encoded heightmap")
-        (@arg nocollide: --nocollide "Disable brick collision")
+        (@arg hdmap: --hdmap "Using a high detail rgb color encoded heightmap, the same as --decode rgba32")
+        (@arg decode: --decode +takes_value "How heights are stored in the heightmap pixels: gray, r16, rgb24, rgba32, terrain-rgb or terrarium (default gray)")
+        (@arg elevation_scale: --("elevation-scale") +takes_value "Heights per meter for terrain-rgb and terrarium tiles (default 10)")
+        (@arg sea_level: --("sea-level") +takes_value "Meters added to terrain-rgb and terrarium elevations to keep ground below sea level (default 0)")
+        (@arg blend: --blend +takes_value "How each heightmap is combined with the ones before it: add, max, min, multiply or detail with an optional :weight, such as add,detail:0.3 (default add)")
+        (@arg nocollide: --nocollide "Disable player, weapon and interact collision")
+        (@arg collision: --collision +takes_value "Collision channels of the bricks: all, none, or a list of player, weapon, interact, tool and physics (default all)")
+        (@arg collision_below: --("collision-below") +takes_value "Collision channels for heights lower than a level, such as 12=none for water")
+        (@arg collision_above: --("collision-above") +takes_value "Collision channels for heights higher than a level, such as 200=tool for decorative peaks")
+        (@arg collision_mask: --("collision-mask") +takes_value "Input mask PNG image, white pixels get the --collision-mask-channels")
+        (@arg collision_mask_channels: --("collision-mask-channels") +takes_value "Collision channels for the white pixels of the collision mask (default none)")
+        (@arg render: --render +takes_value "Render a 3D preview of the bricks to a PNG image")
+        (@arg angle: --angle +takes_value "Camera yaw and pitch for --render in degrees (default 35,40)")
+        (@arg strata: --strata +takes_value "Color the ground under the surface: depths in units of the colormap and subsoil layers, such as 8,40")
+        (@arg subsoil_color: --("subsoil-color") +takes_value requires[strata] "Hex color of the subsoil layer for --strata (default #6b4c30)")
+        (@arg rock_color: --("rock-color") +takes_value requires[strata] "Hex color of the rock under the subsoil for --strata (default #707070)")
+        (@arg owner_name: --("owner-name") +takes_value requires[owner_id] "Name of the owner to give the bricks to, bricks are public by default")
+        (@arg owner_id: --("owner-id") +takes_value "UUID of the owner to give the bricks to")
+        (@arg merge: --merge +takes_value "Add the bricks to an existing save (BRZ, BRDB) instead of a new one")
+        (@arg merge_owner: --("merge-owner") +takes_value "Name or id of an owner in the merged save to give the bricks to")
+        (@arg remove_overlap: --("remove-overlap") "Remove bricks in the merged save that overlap the new bricks")
+        (@arg drop_components: --("drop-components") "Merge into saves with components, wires or entities by dropping them")
+        (@arg gray16: --("16bit") "Write a 16 bit heightmap when converting a save back to images")
+        (@arg crop: --crop +takes_value "Only generate the x,y,w,h pixel region of the maps")
+        (@arg rotate: --rotate +takes_value "Rotate the maps clockwise by 0, 90, 180 or 270 degrees")
+        (@arg flip_x: --("flip-x") "Mirror the maps horizontally")
+        (@arg flip_y: --("flip-y") "Mirror the maps vertically")
+        (@arg resize: --resize +takes_value "Resample the maps to WxH pixels before generating")
+        (@arg target_studs: --("target-studs") +takes_value "Resample the maps so their longest side is this many studs")
+        (@arg height_filter: --("height-filter") +takes_value possible_value[nearest bilinear bicubic area lanczos] "Filter for resampling heights (default bilinear)")
+        (@arg color_filter: --("color-filter") +takes_value possible_value[nearest bilinear bicubic area lanczos] "Filter for resampling colors (default area)")
+        (@arg despeckle: --despeckle "Flatten single pixel spikes and pits in the heightmap")
+        (@arg median: --median +takes_value "Median filter the heights with this radius in pixels")
+        (@arg gaussian: --gaussian +takes_value "Blur the heights with this standard deviation in pixels")
+        (@arg compare_smoothing: --("compare-smoothing") "Also generate without smoothing and log how many merges it enabled")
+        (@arg normalize: --normalize +takes_value "Stretch the lowest and highest heights to min,max")
+        (@arg clamp: --clamp +takes_value "Clamp heights to min,max before remapping")
+        (@arg gamma: --gamma +takes_value "Height curve exponent, above 1 flattens lowlands and exaggerates peaks (default 1)")
+        (@arg curve: --curve +takes_value "Piecewise-linear height curve file with an input,output point from 0 to 1 per line")
+        (@arg terrace: --terrace +takes_value "Round heights into this many steps")
+        (@arg max_size: --("max-size") +takes_value "Largest brick size along x and y, 5 per stud (default 500)")
+        (@arg max_height: --("max-height") +takes_value "Largest height of a single brick in a column (default 250)")
+        (@arg center: --center "Place the center of the map at the origin instead of its corner")
+        (@arg offset: --offset +takes_value visible_alias("merge-offset") "Move the bricks by x,y,z units, also when merging")
+        (@arg stud_offset: --("stud-offset") +takes_value "Move the bricks by x,y,z studs (10 units each)")
+        (@arg base_z: --("base-z") +takes_value "Z in units of the bottom of the lowest brick")
+        (@arg name: --name +takes_value "Name of the save")
+        (@arg description: --description +takes_value "Description of the save, the generator options are added after it")
+        (@arg author: --author +takes_value "Author of the save")
+        (@arg screenshot: --screenshot +takes_value "Preview image of the save: colormap, render, none, or an image file (default colormap)")
+        (@arg skip_unchanged: --("skip-unchanged") "Skip generation if the inputs have not changed since the last run")
     )
     .get_matches();
 
-    // get files from matches
+    // inputs named - are read from stdin
+    let stdin = if matches
+        .values_of("INPUT")
+        .unwrap()
+        .chain(matches.value_of("colormap"))
+        .chain(matches.value_of("mask"))
+        .chain(matches.value_of("collision_mask"))
+        .any(|f| f == "-")
+    {
+        match Source::from_reader(io::stdin()) {
+            Ok(source) => Some(source),
+            Err(err) => {
+                return error!("Error reading stdin: {err}");
+            }
+        }
+    } else {
+        None
+    };
+    let source = |name: &str| match (name, &stdin) {
+        ("-", Some(stdin)) => stdin.clone(),
+        _ => Source::from(name),
+    };
+
+    // get sources from matches
     let heightmap_files = matches
         .values_of("INPUT")
         .unwrap()
-        .map(|s| PathBuf::from(s))
+        .map(source)
         .collect::<Vec<_>>();
-    let colormap_file = matches
-        .value_of("colormap")
-        .map(PathBuf::from)
-        .unwrap_or(heightmap_files[0].clone());
-    let out_file = matches
-        .value_of("output")
-        .unwrap_or("./out.brz")
-        .to_string();
+    let colormap_file = matches.value_of("colormap").map(source);
+    let mask_file = matches.value_of("mask").map(source);
+    let collision_mask_file = matches.value_of("collision_mask").map(source);
+    let out_file = matches.value_of("output").unwrap_or("./out.brz");
 
     // output options
     let mut options = GenOptions {
-        size: matches
-            .value_of("size")
-            .unwrap_or("1")
-            .parse::<u16>()
-            .expect("Size must be integer")
-            * 5,
+        size: {
+            let size = matches.value_of("size").unwrap_or("1");
+            let (x, y) = size.split_once(',').unwrap_or((size, size));
+            let parse = |s: &str| {
+                s.trim()
+                    .parse::<u16>()
+                    .expect("Size must be integer or x,y")
+                    * 5
+            };
+            (parse(x), parse(y))
+        },
         scale: matches
             .value_of("vertical")
             .unwrap_or("1")
@@ -75,83 +162,334 @@ fn main() {
         snap: matches.is_present("snap"),
         img: matches.is_present("img"),
         glow: matches.is_present("glow"),
-        hdmap: matches.is_present("hdmap"),
+        decoding: match matches.value_of("decode") {
+            Some(decoding) => decoding.parse().expect("Invalid height decoding"),
+            None if matches.is_present("hdmap") => Decoding::Rgba32,
+            None => Decoding::Gray,
+        },
+        elevation: Elevation {
+            scale: matches
+                .value_of("elevation_scale")
+                .unwrap_or("10")
+                .parse::<f32>()
+                .expect("Elevation scale must be a number"),
+            sea_level: matches
+                .value_of("sea_level")
+                .unwrap_or("0")
+                .parse::<f32>()
+                .expect("Sea level must be a number"),
+        },
+        blends: matches
+            .value_of("blend")
+            .map(|blends| {
+                blends
+                    .split(',')
+                    .map(|b| b.parse().expect("Invalid blend"))
+                    .collect()
+            })
+            .unwrap_or_default(),
         lrgb: matches.is_present("lrgb"),
-        nocollide: matches.is_present("nocollide"),
+        collision: CollisionRules {
+            channels: match matches.value_of("collision") {
+                Some(c) => c.parse().expect("Invalid collision channels"),
+                None if matches.is_present("nocollide") => Channels::NOCOLLIDE,
+                None => Channels::ALL,
+            },
+            below: matches
+                .value_of("collision_below")
+                .map(|r| parse_region(r).expect("Collision below must be height=channels")),
+            above: matches
+                .value_of("collision_above")
+                .map(|r| parse_region(r).expect("Collision above must be height=channels")),
+            mask: matches
+                .value_of("collision_mask_channels")
+                .map(|c| c.parse().expect("Invalid collision mask channels"))
+                .unwrap_or(Channels::NONE),
+        },
         quadtree: true,
+        anchor: if matches.is_present("center") {
+            Anchor::Center
+        } else {
+            Anchor::Corner
+        },
+        offset: (0, 0, 0),
+        base_z: matches
+            .value_of("base_z")
+            .map(|z| z.parse::<i32>().expect("Base Z must be integer")),
+        crop: matches
+            .value_of("crop")
+            .map(|crop| parse_crop(crop).expect("Crop must be x,y,w,h")),
+        transform: Transform {
+            rotate: match matches.value_of("rotate").unwrap_or("0") {
+                "0" => 0,
+                "90" => 1,
+                "180" => 2,
+                "270" => 3,
+                _ => panic!("Rotation must be 0, 90, 180 or 270"),
+            },
+            flip_x: matches.is_present("flip_x"),
+            flip_y: matches.is_present("flip_y"),
+        },
+        resize: None,
+        height_filter: matches
+            .value_of("height_filter")
+            .map(|f| f.parse().expect("Invalid height filter"))
+            .unwrap_or(Filter::Bilinear),
+        color_filter: matches
+            .value_of("color_filter")
+            .map(|f| f.parse().expect("Invalid color filter"))
+            .unwrap_or(Filter::Area),
+        max_brick_size: matches
+            .value_of("max_size")
+            .unwrap_or("500")
+            .parse::<u16>()
+            .expect("Max size must be integer"),
+        max_brick_height: matches
+            .value_of("max_height")
+            .unwrap_or("250")
+            .parse::<u16>()
+            .expect("Max height must be integer"),
+        owner: None,
+        strata: matches.value_of("strata").map(|depths| {
+            let (surface, subsoil) = parse_range(depths).expect("Strata must be surface,subsoil");
+            let mut strata = Strata {
+                surface,
+                subsoil,
+                ..Default::default()
+            };
+            if let Some(color) = matches.value_of("subsoil_color") {
+                strata.subsoil_color = parse_color(color).expect("Invalid subsoil color");
+            }
+            if let Some(color) = matches.value_of("rock_color") {
+                strata.rock_color = parse_color(color).expect("Invalid rock color");
+            }
+            strata
+        }),
+        smooth: Smooth {
+            despeckle: matches.is_present("despeckle"),
+            median: matches
+                .value_of("median")
+                .map(|r| r.parse::<u32>().expect("Median radius must be integer")),
+            gaussian: matches
+                .value_of("gaussian")
+                .map(|s| s.parse::<f32>().expect("Gaussian sigma must be a number")),
+            compare: matches.is_present("compare_smoothing"),
+        },
+        remap: Remap {
+            clamp: matches
+                .value_of("clamp")
+                .map(|r| parse_range(r).expect("Clamp must be min,max")),
+            normalize: matches
+                .value_of("normalize")
+                .map(|r| parse_range(r).expect("Normalize must be min,max")),
+            gamma: matches
+                .value_of("gamma")
+                .unwrap_or("1")
+                .parse::<f32>()
+                .expect("Gamma must be a number"),
+            curve: None,
+            terrace: matches
+                .value_of("terrace")
+                .map(|n| n.parse::<u32>().expect("Terrace must be integer")),
+        },
     };
 
+    if let Some(path) = matches.value_of("curve") {
+        match Curve::from_file(path) {
+            Ok(curve) => options.remap.curve = Some(curve),
+            Err(err) => {
+                return error!("{err}");
+            }
+        }
+    }
+
+    if let Some(size) = matches.value_of("resize") {
+        let (w, h) = size
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+            .expect("Resize must be WxH");
+        options.resize = Some(Resize::Pixels(w, h));
+    } else if let Some(studs) = matches.value_of("target_studs") {
+        let studs = studs.parse::<u32>().expect("Target studs must be integer");
+        options.resize = Some(Resize::Studs(studs));
+    }
+
+    if let Some(offset) = matches.value_of("offset") {
+        options.offset = parse_xyz(offset).expect("Offset must be x,y,z");
+    }
+    if let Some(offset) = matches.value_of("stud_offset") {
+        let (x, y, z) = parse_xyz(offset).expect("Stud offset must be x,y,z");
+        options.offset = (
+            options.offset.0 + x * 10,
+            options.offset.1 + y * 10,
+            options.offset.2 + z * 10,
+        );
+    }
+
+    if let Some(id) = matches.value_of("owner_id") {
+        let id = parse_guid(id).expect("Owner id must be a UUID");
+        options.owner = Some(BrickOwner::new(matches.value_of("owner_name"), id));
+    }
+
     if options.tile {
         options.asset = PB_DEFAULT_TILE;
     } else if options.micro {
-        options.size /= 5;
+        options.size = (options.size.0 / 5, options.size.1 / 5);
         options.asset = PB_DEFAULT_MICRO_BRICK;
     }
     if options.stud {
         options.asset = PB_DEFAULT_STUDDED;
     }
 
-    info!("Reading image files");
-
-    // colormap file parsing
-    let colormap = match file_ext(&colormap_file) {
-        Some("png") => match ColormapPNG::new(&colormap_file, options.lrgb) {
-            Ok(map) => map,
-            Err(err) => {
-                return error!("Error reading colormap: {:?}", err);
-            }
-        },
-        Some(ext) => {
-            return error!("Unsupported colormap format '{}'", ext);
+    // saves given as input are converted back into a heightmap and colormap
+    let inputs = matches.values_of("INPUT").unwrap().collect::<Vec<_>>();
+    if let Some(&input) = inputs
+        .iter()
+        .find(|input| matches!(Format::from_path(input), Ok(Format::Brz | Format::Brdb)))
+    {
+        if inputs.len() > 1 {
+            return error!(
+                "A save input can not be combined with other inputs, convert one save at a time"
+            );
         }
-        None => {
-            return error!("Missing colormap format for '{}'", colormap_file.display());
+        // only gray and rgba32 heightmaps can be written back
+        if !matches!(options.decoding, Decoding::Gray | Decoding::Rgba32) {
+            return error!(
+                "A save can only be converted to a gray or rgba32 heightmap, not --decode {}",
+                options.decoding.name()
+            );
         }
-    };
-
-    // heightmap file parsing
-    let heightmap: Box<dyn Heightmap> =
-        if heightmap_files.iter().all(|f| file_ext(f) == Some("png")) {
-            if options.img {
-                Box::new(HeightmapFlat::new(colormap.size()).unwrap())
+        let options = ExtractOptions {
+            size: options.size,
+            scale: options.scale,
+            encoding: if options.decoding == Decoding::Rgba32 {
+                HeightEncoding::Rgba
+            } else if matches.is_present("gray16") {
+                HeightEncoding::Gray16
             } else {
-                match HeightmapPNG::new(heightmap_files.iter().collect(), options.hdmap) {
-                    Ok(map) => Box::new(map),
-                    Err(error) => {
-                        return error!("Error reading heightmap: {:?}", error);
-                    }
-                }
+                HeightEncoding::Gray8
+            },
+            lrgb: options.lrgb,
+        };
+        let (heightmap, colormap) = match extract_save(input, &options) {
+            Ok(maps) => maps,
+            Err(err) => {
+                return error!("{err}");
             }
-        } else {
-            return error!("Unsupported heightmap format");
         };
 
-    let bricks = gen_opt_heightmap(&*heightmap, &colormap, options, |_| true)
-        .expect("error during generation");
-
-    info!("Writing Save to {}", out_file);
-    let data = bricks_to_save(bricks);
-    if out_file.to_lowercase().ends_with(".brz") {
-        let brz = match data.to_brz_vec() {
-            Ok(b) => b,
-            Err(e) => {
-                error!("failed to encode brz: {e}");
-                return;
+        let out_file = matches.value_of("output").unwrap_or("./out.png");
+        info!("Writing heightmap to {out_file}");
+        if let Err(err) = heightmap.save(out_file) {
+            return error!("could not write image {out_file}: {err}");
+        }
+        if let Some(colormap_file) = matches.value_of("colormap") {
+            info!("Writing colormap to {colormap_file}");
+            if let Err(err) = colormap.save(colormap_file) {
+                return error!("could not write image {colormap_file}: {err}");
             }
-        };
-        if let Err(e) = std::fs::write(&out_file, brz) {
-            error!("failed to write file: {e}");
-            return;
         }
-    } else if out_file.to_lowercase().ends_with(".brdb") {
-        if let Err(e) = data.write_brdb(&out_file) {
-            error!("failed to write file: {e}");
-            return;
-        };
-    } else {
-        error!("output file must end with .brz or .brdb");
-        return;
+        return info!("Done!");
+    }
+
+    let format = matches
+        .value_of("format")
+        .map(|f| f.parse::<Format>().expect("Invalid format"));
+    let output = match (out_file, format) {
+        ("-", format) => Output::writer(io::stdout(), format.unwrap_or(Format::Brz)),
+        (path, Some(format)) => Ok(Output::File(PathBuf::from(path), format)),
+        (path, None) => Output::from_path(path),
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            return error!("{err}");
+        }
+    };
+
+    let render = matches.value_of("render").map(|path| {
+        let mut camera = Camera::default();
+        if let Some(angle) = matches.value_of("angle") {
+            let (yaw, pitch) = angle
+                .split_once(',')
+                .and_then(|(yaw, pitch)| {
+                    Some((yaw.parse::<f32>().ok()?, pitch.parse::<f32>().ok()?))
+                })
+                .expect("Angle must be yaw,pitch");
+            camera.yaw = yaw.to_radians();
+            camera.pitch = pitch.to_radians();
+        }
+        Render {
+            path: PathBuf::from(path),
+            camera,
+            size: (1024, 768),
+        }
+    });
+
+    let merge = matches.value_of("merge").map(|path| {
+        let mut merge = Merge::new(path);
+        merge.owner = matches.value_of("merge_owner").map(str::to_string);
+        merge.remove_overlap = matches.is_present("remove_overlap");
+        merge.drop_components = matches.is_present("drop_components");
+        merge
+    });
+
+    let meta = SaveMeta {
+        name: matches.value_of("name").map(str::to_string),
+        description: matches.value_of("description").map(str::to_string),
+        author: matches.value_of("author").map(str::to_string),
+        screenshot: matches.value_of("screenshot").map(|s| match s {
+            "colormap" => Screenshot::Colormap,
+            "render" => Screenshot::Render,
+            "none" => Screenshot::None,
+            path => Screenshot::Image(Source::from(path)),
+        }),
+    };
+
+    if let Err(err) = Converter::new(options, output)
+        .heightmaps(heightmap_files)
+        .colormap(colormap_file)
+        .mask(mask_file)
+        .collision_mask(collision_mask_file)
+        .skip_unchanged(matches.is_present("skip_unchanged"))
+        .render(render)
+        .merge(merge)
+        .meta(meta)
+        .run(|_, _| true)
+    {
+        return error!("{err}");
     }
 
     info!("Done!");
 }
+
+// parse a comma separated x,y,z triple
+fn parse_xyz(s: &str) -> Option<(i32, i32, i32)> {
+    let mut parts = s.split(',').map(|n| n.trim().parse::<i32>().ok());
+    let xyz = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(xyz)
+}
+
+// parse a comma separated x,y,w,h region
+fn parse_crop(s: &str) -> Option<Crop> {
+    let mut parts = s.split(',').map(|n| n.trim().parse::<u32>().ok());
+    let crop = Crop {
+        x: parts.next()??,
+        y: parts.next()??,
+        width: parts.next()??,
+        height: parts.next()??,
+    };
+    parts.next().is_none().then_some(crop)
+}
+
+// parse a comma separated min,max range
+fn parse_range(s: &str) -> Option<(u32, u32)> {
+    let (min, max) = s.split_once(',')?;
+    Some((min.trim().parse().ok()?, max.trim().parse().ok()?))
+}
+
+// parse a height=channels collision region
+fn parse_region(s: &str) -> Option<(u32, Channels)> {
+    let (height, channels) = s.split_once('=')?;
+    Some((height.trim().parse().ok()?, channels.parse().ok()?))
+}
@@ -1,10 +1,86 @@
-use image::RgbaImage;
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, RgbaImage};
 use std::{
+    hash::{Hash, Hasher},
+    io::{self, Read},
     path::{Path, PathBuf},
-    result::Result,
+    str::FromStr,
 };
 
-use crate::util::to_linear_rgb;
+use crate::{
+    Error,
+    blend::Blend,
+    util::{Crop, Transform, to_linear_rgb},
+};
+
+// An image input, either a file, an encoded image in memory, or an already decoded image
+#[derive(Clone)]
+pub enum Source {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+    Image(DynamicImage),
+}
+
+impl Source {
+    // read an encoded image from a reader, such as stdin
+    pub fn from_reader(mut reader: impl Read) -> io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        Ok(Source::Bytes(bytes))
+    }
+
+    // decode the image keeping its pixel format
+    pub fn decode(&self) -> Result<DynamicImage, Error> {
+        match self {
+            Source::File(path) => image::open(path).map_err(|source| Error::Image {
+                path: path.clone(),
+                source,
+            }),
+            Source::Bytes(bytes) => image::load_from_memory(bytes).map_err(Error::Decode),
+            Source::Image(img) => Ok(img.clone()),
+        }
+    }
+
+    // decode the image into rgba pixels
+    pub fn load(&self) -> Result<RgbaImage, Error> {
+        self.decode().map(DynamicImage::into_rgba8)
+    }
+}
+
+impl From<PathBuf> for Source {
+    fn from(path: PathBuf) -> Self {
+        Source::File(path)
+    }
+}
+
+impl From<&Path> for Source {
+    fn from(path: &Path) -> Self {
+        Source::File(path.to_path_buf())
+    }
+}
+
+impl From<&str> for Source {
+    fn from(path: &str) -> Self {
+        Source::File(PathBuf::from(path))
+    }
+}
+
+impl From<Vec<u8>> for Source {
+    fn from(bytes: Vec<u8>) -> Self {
+        Source::Bytes(bytes)
+    }
+}
+
+impl From<DynamicImage> for Source {
+    fn from(img: DynamicImage) -> Self {
+        Source::Image(img)
+    }
+}
+
+impl From<RgbaImage> for Source {
+    fn from(img: RgbaImage) -> Self {
+        Source::Image(DynamicImage::ImageRgba8(img))
+    }
+}
 
 // generic heightmap trait returns scalar from X and Y
 pub trait Heightmap {
@@ -18,59 +94,280 @@ pub trait Colormap {
     fn size(&self) -> (u32, u32);
 }
 
+// generic mask trait returns whether to generate the pixel at X and Y
+pub trait Mask {
+    fn at(&self, x: u32, y: u32) -> bool;
+    fn size(&self) -> (u32, u32);
+}
+
+// how heights are stored in the pixels of a heightmap image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Decoding {
+    // the red channel of a grayscale image
+    #[default]
+    Gray,
+    // 16 bits with the high byte in red and the low byte in green
+    R16,
+    // 24 bits split across red, green and blue
+    Rgb24,
+    // 32 bits split across red, green, blue and alpha, the --hdmap format
+    Rgba32,
+    // Mapbox Terrain-RGB tiles, in meters above sea level
+    TerrainRgb,
+    // Terrarium tiles, in meters above sea level
+    Terrarium,
+}
+
+impl Decoding {
+    pub const ALL: [Decoding; 6] = [
+        Decoding::Gray,
+        Decoding::R16,
+        Decoding::Rgb24,
+        Decoding::Rgba32,
+        Decoding::TerrainRgb,
+        Decoding::Terrarium,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Decoding::Gray => "gray",
+            Decoding::R16 => "r16",
+            Decoding::Rgb24 => "rgb24",
+            Decoding::Rgba32 => "rgba32",
+            Decoding::TerrainRgb => "terrain-rgb",
+            Decoding::Terrarium => "terrarium",
+        }
+    }
+
+    // read the height of a pixel, elevations in meters are converted by the elevation options
+    pub fn decode(self, [r, g, b, a]: [u8; 4], elevation: Elevation) -> u32 {
+        let rgb = u32::from_be_bytes([0, r, g, b]);
+        match self {
+            Decoding::Gray => r as u32,
+            Decoding::R16 => u16::from_be_bytes([r, g]) as u32,
+            Decoding::Rgb24 => rgb,
+            Decoding::Rgba32 => u32::from_be_bytes([r, g, b, a]),
+            // -10000 + rgb * 0.1
+            Decoding::TerrainRgb => elevation.height(rgb as f64 * 0.1 - 10000.0),
+            // r * 256 + g + b / 256 - 32768
+            Decoding::Terrarium => elevation.height(rgb as f64 / 256.0 - 32768.0),
+        }
+    }
+
+    // read the height of a 16 bit grayscale pixel, gray and r16 keep all 16 bits
+    // and the other decodings see the 8 bit gray value
+    pub fn decode16(self, v: u16, elevation: Elevation) -> u32 {
+        match self {
+            Decoding::Gray | Decoding::R16 => v as u32,
+            _ => {
+                let [c, _] = v.to_be_bytes();
+                self.decode([c, c, c, u8::MAX], elevation)
+            }
+        }
+    }
+
+    // the highest height a pixel can have
+    pub fn max(self, elevation: Elevation) -> u32 {
+        self.decode([u8::MAX; 4], elevation)
+    }
+
+    // whether the pixels hold elevations in meters
+    pub fn is_elevation(self) -> bool {
+        matches!(self, Decoding::TerrainRgb | Decoding::Terrarium)
+    }
+}
+
+// how elevations in meters from terrain-rgb and terrarium tiles become heights
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Elevation {
+    // heights per meter, 10 keeps the decimeter steps of terrain-rgb tiles
+    pub scale: f32,
+    // meters added to every elevation, so ground down to this depth below sea level is kept
+    pub sea_level: f32,
+}
+
+impl Default for Elevation {
+    fn default() -> Self {
+        Elevation {
+            scale: 10.0,
+            sea_level: 0.0,
+        }
+    }
+}
+
+impl Elevation {
+    // the height of an elevation, anything below the sea level offset is 0
+    pub fn height(&self, meters: f64) -> u32 {
+        let height = (meters + self.sea_level as f64) * self.scale as f64;
+        height.round().clamp(0.0, u32::MAX as f64) as u32
+    }
+}
+
+impl Hash for Elevation {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.scale.to_bits().hash(state);
+        self.sea_level.to_bits().hash(state);
+    }
+}
+
+impl FromStr for Decoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        Decoding::ALL
+            .into_iter()
+            .find(|d| d.name() == name)
+            .ok_or_else(|| Error::UnknownDecoding(s.to_string()))
+    }
+}
+
+// A heightmap image, 16 bit grayscale images keep their full precision
+pub enum HeightImage {
+    Rgba(RgbaImage),
+    Gray16(ImageBuffer<Luma<u16>, Vec<u16>>),
+}
+
+impl HeightImage {
+    fn height(&self, x: u32, y: u32, decoding: Decoding, elevation: Elevation) -> u32 {
+        match self {
+            HeightImage::Rgba(img) => decoding.decode(img.get_pixel(x, y).0, elevation),
+            HeightImage::Gray16(img) => decoding.decode16(img.get_pixel(x, y).0[0], elevation),
+        }
+    }
+
+    // the highest height a pixel of this image can have
+    fn max(&self, decoding: Decoding, elevation: Elevation) -> u32 {
+        match self {
+            HeightImage::Rgba(_) => decoding.max(elevation),
+            HeightImage::Gray16(_) => decoding.decode16(u16::MAX, elevation),
+        }
+    }
+
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            HeightImage::Rgba(img) => img.dimensions(),
+            HeightImage::Gray16(img) => img.dimensions(),
+        }
+    }
+}
+
+impl From<RgbaImage> for HeightImage {
+    fn from(img: RgbaImage) -> Self {
+        HeightImage::Rgba(img)
+    }
+}
+
+impl From<DynamicImage> for HeightImage {
+    fn from(img: DynamicImage) -> Self {
+        match img {
+            DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) => {
+                HeightImage::Gray16(img.into_luma16())
+            }
+            img => HeightImage::Rgba(img.into_rgba8()),
+        }
+    }
+}
+
 // PNG based heightmaps
 pub struct HeightmapPNG {
-    maps: Vec<RgbaImage>,
-    rgba_encoded: bool,
+    maps: Vec<HeightImage>,
+    decoding: Decoding,
+    elevation: Elevation,
+    // how each map is combined with the ones before it, the maps are summed without any
+    blends: Vec<Blend>,
 }
 
 // Heightmap lookup
 impl Heightmap for HeightmapPNG {
     fn at(&self, x: u32, y: u32) -> u32 {
-        if self.rgba_encoded {
-            self.maps
-                .iter()
-                .fold(0, |sum, m| sum + u32::from_be_bytes(m.get_pixel(x, y).0))
-        } else {
-            self.maps
-                .iter()
-                .fold(0, |sum, m| sum + m.get_pixel(x, y).0[0] as u32)
+        let height = |m: &HeightImage| m.height(x, y, self.decoding, self.elevation);
+        if self.blends.is_empty() {
+            return self.maps.iter().fold(0, |sum, m| sum + height(m));
         }
+
+        let base = height(&self.maps[0]) as f64 * self.blends[0].weight as f64;
+        let blended =
+            self.maps[1..]
+                .iter()
+                .zip(&self.blends[1..])
+                .fold(base, |below, (m, blend)| {
+                    let max = m.max(self.decoding, self.elevation) as f64;
+                    blend.apply(below, height(m) as f64, max)
+                });
+        blended.round().clamp(0.0, u32::MAX as f64) as u32
     }
 
     fn size(&self) -> (u32, u32) {
-        (self.maps[0].width(), self.maps[0].height())
+        self.maps[0].dimensions()
     }
 }
 
 // Heightmap image input
 impl HeightmapPNG {
-    pub fn new(images: Vec<&PathBuf>, rgba_encoded: bool) -> Result<Self, String> {
-        if images.is_empty() {
-            return Err("HeightmapPNG requires at least one image".to_string());
-        }
+    pub fn new(images: Vec<&PathBuf>, decoding: Decoding) -> Result<Self, Error> {
+        let sources = images
+            .into_iter()
+            .map(|file| Source::File(file.clone()))
+            .collect::<Vec<_>>();
+        Self::from_sources(&sources, decoding)
+    }
 
-        // read in the maps
-        let mut maps: Vec<RgbaImage> = vec![];
-        for file in images {
-            if let Ok(img) = image::open(file) {
-                maps.push(img.to_rgba8());
-            } else {
-                return Err(format!("Could not open PNG {}", file.display()));
-            }
+    // read in the maps from files, buffers, or images
+    pub fn from_sources(sources: &[Source], decoding: Decoding) -> Result<Self, Error> {
+        let maps = sources
+            .iter()
+            .map(Source::decode)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_images(maps, decoding)
+    }
+
+    // use already decoded images as the maps
+    pub fn from_images(
+        maps: Vec<impl Into<HeightImage>>,
+        decoding: Decoding,
+    ) -> Result<Self, Error> {
+        let maps = maps.into_iter().map(Into::into).collect::<Vec<_>>();
+        if maps.is_empty() {
+            return Err(Error::NoHeightmaps);
         }
 
         // check to ensure all images have the same dimensions
-        let height = maps[0].height();
-        let width = maps[0].width();
+        let expected = maps[0].dimensions();
         for m in &maps {
-            if m.height() != height || m.width() != width {
-                return Err("Mismatched heightmap sizes".to_string());
+            if m.dimensions() != expected {
+                return Err(Error::MismatchedHeightmaps {
+                    expected,
+                    found: m.dimensions(),
+                });
             }
         }
 
         // return a reference to save on memory
-        Ok(HeightmapPNG { maps, rgba_encoded })
+        Ok(HeightmapPNG {
+            maps,
+            decoding,
+            elevation: Elevation::default(),
+            blends: vec![],
+        })
+    }
+
+    // convert elevation decodings with these options instead of the defaults
+    pub fn elevation(mut self, elevation: Elevation) -> Self {
+        self.elevation = elevation;
+        self
+    }
+
+    // combine the maps with these blends instead of summing them, one blend per map
+    pub fn blend(mut self, blends: Vec<Blend>) -> Result<Self, Error> {
+        if !blends.is_empty() && blends.len() != self.maps.len() {
+            return Err(Error::MismatchedBlends {
+                blends: blends.len(),
+                heightmaps: self.maps.len(),
+            });
+        }
+        self.blends = blends;
+        Ok(self)
     }
 }
 
@@ -93,7 +390,7 @@ impl Heightmap for HeightmapFlat {
 
 // Flat heightmap just has dimensions
 impl HeightmapFlat {
-    pub fn new((width, height): (u32, u32)) -> Result<Self, String> {
+    pub fn new((width, height): (u32, u32)) -> Result<Self, Error> {
         // return a reference to save on memory
         Ok(HeightmapFlat { width, height })
     }
@@ -122,14 +419,173 @@ impl Colormap for ColormapPNG {
 
 // Colormap image input
 impl ColormapPNG {
-    pub fn new(file: impl AsRef<Path>, lrgb: bool) -> Result<Self, String> {
-        if let Ok(img) = image::open(&file) {
-            Ok(ColormapPNG {
-                source: img.to_rgba8(),
-                lrgb,
-            })
-        } else {
-            Err(format!("Could not open PNG {}", file.as_ref().display()))
+    pub fn new(file: impl AsRef<Path>, lrgb: bool) -> Result<Self, Error> {
+        Self::from_source(&Source::from(file.as_ref()), lrgb)
+    }
+
+    // read in the colormap from a file, buffer, or image
+    pub fn from_source(source: &Source, lrgb: bool) -> Result<Self, Error> {
+        Ok(Self::from_image(source.load()?, lrgb))
+    }
+
+    // use an already decoded image as the colormap
+    pub fn from_image(source: RgbaImage, lrgb: bool) -> Self {
+        ColormapPNG { source, lrgb }
+    }
+}
+
+// PNG based mask, black or transparent pixels are skipped
+pub struct MaskPNG {
+    source: GrayImage,
+}
+
+impl Mask for MaskPNG {
+    fn at(&self, x: u32, y: u32) -> bool {
+        self.source.get_pixel(x, y).0[0] >= 128
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.source.dimensions()
+    }
+}
+
+// Mask image input
+impl MaskPNG {
+    // read in the mask from a file, buffer, or image
+    pub fn from_source(source: &Source) -> Result<Self, Error> {
+        Ok(Self::from_image(&source.load()?))
+    }
+
+    // use an already decoded image as the mask
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let source = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            let [r, g, b, a] = image.get_pixel(x, y).0;
+            let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+            Luma([(luma * a as u32 / 255) as u8])
+        });
+        MaskPNG { source }
+    }
+}
+
+// A map sampled through a rotation and flips
+pub struct Transformed<M> {
+    inner: M,
+    transform: Transform,
+}
+
+impl<M> Transformed<M> {
+    pub fn new(inner: M, transform: Transform) -> Self {
+        Transformed { inner, transform }
+    }
+}
+
+impl Heightmap for Transformed<Box<dyn Heightmap>> {
+    fn at(&self, x: u32, y: u32) -> u32 {
+        let (x, y) = self.transform.source((x, y), self.inner.size());
+        self.inner.at(x, y)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.transform.size(self.inner.size())
+    }
+}
+
+impl Colormap for Transformed<Box<dyn Colormap>> {
+    fn at(&self, x: u32, y: u32) -> [u8; 4] {
+        let (x, y) = self.transform.source((x, y), self.inner.size());
+        self.inner.at(x, y)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.transform.size(self.inner.size())
+    }
+}
+
+impl Mask for Transformed<Box<dyn Mask>> {
+    fn at(&self, x: u32, y: u32) -> bool {
+        let (x, y) = self.transform.source((x, y), self.inner.size());
+        self.inner.at(x, y)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.transform.size(self.inner.size())
+    }
+}
+
+// A region of another map, coordinates are offset into the inner map
+pub struct Cropped<M> {
+    inner: M,
+    crop: Crop,
+}
+
+impl<M> Cropped<M> {
+    pub fn new(inner: M, crop: Crop, size: (u32, u32)) -> Result<Self, Error> {
+        if crop.width == 0
+            || crop.height == 0
+            || crop.x.saturating_add(crop.width) > size.0
+            || crop.y.saturating_add(crop.height) > size.1
+        {
+            return Err(Error::CropOutOfBounds {
+                crop: (crop.x, crop.y, crop.width, crop.height),
+                size,
+            });
         }
+        Ok(Cropped { inner, crop })
+    }
+}
+
+impl Heightmap for Cropped<Box<dyn Heightmap>> {
+    fn at(&self, x: u32, y: u32) -> u32 {
+        self.inner.at(x + self.crop.x, y + self.crop.y)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.crop.width, self.crop.height)
+    }
+}
+
+impl Colormap for Cropped<Box<dyn Colormap>> {
+    fn at(&self, x: u32, y: u32) -> [u8; 4] {
+        self.inner.at(x + self.crop.x, y + self.crop.y)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.crop.width, self.crop.height)
+    }
+}
+
+impl Mask for Cropped<Box<dyn Mask>> {
+    fn at(&self, x: u32, y: u32) -> bool {
+        self.inner.at(x + self.crop.x, y + self.crop.y)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.crop.width, self.crop.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elevations_keep_decimeters_and_sea_level() {
+        // 1234.5 meters as terrain-rgb, (h + 10000) * 10 = 0x01b6d9
+        let px = [0x01, 0xb6, 0xd9, 255];
+        assert_eq!(Decoding::TerrainRgb.decode(px, Elevation::default()), 12345);
+        let meters = Elevation {
+            scale: 1.0,
+            sea_level: 0.0,
+        };
+        assert_eq!(Decoding::TerrainRgb.decode(px, meters), 1235);
+
+        // -50.5 meters as terrarium is flattened unless the sea level keeps it
+        let px = [0x7f, 0xcd, 0x80, 255];
+        assert_eq!(Decoding::Terrarium.decode(px, Elevation::default()), 0);
+        let deep = Elevation {
+            sea_level: 100.0,
+            ..Default::default()
+        };
+        assert_eq!(Decoding::Terrarium.decode(px, deep), 495);
     }
 }
@@ -0,0 +1,283 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use brdb::{
+    BrError, BrFsReader, BrReader, Brdb, Brick, Brz, Guid, IntoReader, Owner, OwnerTableSoA, World,
+};
+use log::{info, warn};
+
+use crate::{
+    Error,
+    render::Cuboid,
+    util::{BrickOwner, add_owner, file_ext},
+};
+
+// an existing save that generated bricks are added to
+#[derive(Debug, Clone, Hash)]
+pub struct Merge {
+    pub path: PathBuf,
+    // remove existing bricks that overlap the generated bricks
+    pub remove_overlap: bool,
+    // user name, display name or id of an owner in the save that gets the generated bricks,
+    // generated bricks are public by default
+    pub owner: Option<String>,
+    // drop the components, wires and entities of the save instead of failing,
+    // they cannot be written back yet
+    pub drop_components: bool,
+}
+
+impl Merge {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Merge {
+            path: path.into(),
+            remove_overlap: false,
+            owner: None,
+            drop_components: false,
+        }
+    }
+}
+
+// read the main grid, owners and metadata of a save into a world, a save with components,
+// wires or entities is an error unless they are dropped
+pub fn read_world(path: impl AsRef<Path>, drop_components: bool) -> Result<World, Error> {
+    let path = path.as_ref();
+    let read = || match file_ext(path).map(str::to_lowercase).as_deref() {
+        Some("brz") => read_reader(
+            &Brz::open(path).map_err(BrError::from)?.into_reader(),
+            drop_components,
+        ),
+        Some("brdb") => read_reader(
+            &Brdb::open(path).map_err(BrError::from)?.into_reader(),
+            drop_components,
+        ),
+        Some(ext) => Err(Error::UnsupportedFormat {
+            kind: "save",
+            ext: ext.to_string(),
+        }),
+        None => Err(Error::MissingFormat {
+            kind: "save",
+            path: path.to_path_buf(),
+        }),
+    };
+    read().map_err(|err| match err {
+        Error::Encode(source) => Error::ReadSave {
+            path: path.to_path_buf(),
+            source,
+        },
+        err => err,
+    })
+}
+
+pub(crate) fn read_reader<T: BrFsReader>(
+    db: &BrReader<T>,
+    drop_components: bool,
+) -> Result<World, Error> {
+    let mut world = World::new();
+
+    world.meta.bundle =
+        serde_json::from_slice(&db.read_file("Meta/Bundle.json").map_err(BrError::from)?)
+            .map_err(BrError::from)?;
+    world.meta.screenshot = db.read_file("Meta/Screenshot.jpg").ok();
+    if let Ok(json) = db.read_file("Meta/World.json")
+        && let Ok(meta) = serde_json::from_slice(&json)
+    {
+        world.meta.world = meta;
+    }
+
+    // the public owner is always first in the owner table, so the other owners
+    // are shifted by one when the world is written
+    let owners = OwnerTableSoA::try_from(&db.owners_soa()?.to_value()).map_err(BrError::from)?;
+    let mut owner_map = vec![0; owners.user_ids.len()];
+    for (i, user_id) in owners.user_ids.iter().enumerate() {
+        if *user_id == Guid::default() {
+            continue;
+        }
+        let (index, _) = world.owners.insert_full(
+            *user_id,
+            Owner {
+                user_id: *user_id,
+                user_name: owners.user_names[i].clone(),
+                display_name: owners.display_names[i].clone(),
+            },
+        );
+        owner_map[i] = index + 1;
+    }
+
+    let global_data = db.global_data()?;
+    let (mut components, mut wires) = (0, 0);
+    for chunk in db.brick_chunk_index(1)? {
+        components += chunk.num_components;
+        wires += chunk.num_wires;
+        for brick in db
+            .brick_chunk_soa(1, chunk.index)?
+            .iter_bricks(chunk.index, global_data.clone())
+        {
+            let mut brick = brick.map_err(BrError::from)?;
+            brick.owner_index = brick
+                .owner_index
+                .map(|i| owner_map.get(i).copied().unwrap_or(0));
+            world.bricks.push(brick);
+        }
+    }
+
+    // moving grids are entities too
+    let entities = db
+        .entity_chunk_index_soa()
+        .map(|index| index.num_entities.iter().sum())
+        .unwrap_or(0);
+    if components > 0 || wires > 0 || entities > 0 {
+        if !drop_components {
+            return Err(Error::UnmergeableSave {
+                components,
+                wires,
+                entities,
+            });
+        }
+        warn!(
+            "Dropping {components} components, {wires} wires and {entities} entities from the merged save"
+        );
+    }
+
+    Ok(world)
+}
+
+// find the index an owner will have when the world is written
+fn owner_index(world: &World, owner: &str) -> Option<usize> {
+    world
+        .owners
+        .values()
+        .position(|o| {
+            o.user_name.eq_ignore_ascii_case(owner)
+                || o.display_name.eq_ignore_ascii_case(owner)
+                || o.user_id.uuid().to_string().eq_ignore_ascii_case(owner)
+        })
+        .map(|i| i + 1)
+}
+
+// add bricks to a world, returns the number of existing bricks that were removed,
+// an owner from the save takes priority over a new owner
+pub fn merge_bricks(
+    world: &mut World,
+    mut bricks: Vec<Brick>,
+    merge: &Merge,
+    new_owner: Option<&BrickOwner>,
+) -> Result<usize, Error> {
+    let owner = match (&merge.owner, new_owner) {
+        (Some(owner), _) => {
+            Some(owner_index(world, owner).ok_or_else(|| Error::UnknownOwner(owner.clone()))?)
+        }
+        (None, Some(owner)) => Some(add_owner(world, owner)),
+        (None, None) => None,
+    };
+
+    for brick in &mut bricks {
+        brick.owner_index = owner;
+    }
+
+    let mut removed = 0;
+    if merge.remove_overlap {
+        let before = world.bricks.len();
+        let terrain = Overlap::new(bricks.iter().map(Cuboid::from).collect());
+        world
+            .bricks
+            .retain(|brick| !terrain.overlaps(&Cuboid::from(brick)));
+        removed = before - world.bricks.len();
+        info!("Removed {removed} overlapping bricks");
+    }
+
+    world.bricks.extend(bricks);
+    Ok(removed)
+}
+
+// size of the buckets boxes are sorted into to speed up overlap checks
+const BUCKET_SIZE: f32 = 320.0;
+
+// finds boxes that overlap a set of boxes
+struct Overlap {
+    cuboids: Vec<Cuboid>,
+    buckets: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl Overlap {
+    fn new(cuboids: Vec<Cuboid>) -> Self {
+        let mut buckets = HashMap::<_, Vec<_>>::new();
+        for (i, c) in cuboids.iter().enumerate() {
+            for key in Self::keys(c) {
+                buckets.entry(key).or_default().push(i);
+            }
+        }
+        Overlap { cuboids, buckets }
+    }
+
+    // the buckets a box covers on the horizontal plane
+    fn keys(c: &Cuboid) -> impl Iterator<Item = (i32, i32)> {
+        let [x0, y0] =
+            [0, 1].map(|a| ((c.center[a] - c.half_size[a]) / BUCKET_SIZE).floor() as i32);
+        let [x1, y1] =
+            [0, 1].map(|a| ((c.center[a] + c.half_size[a]) / BUCKET_SIZE).floor() as i32);
+        (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+    }
+
+    // check if a box overlaps any of the boxes, boxes that only touch do not overlap
+    fn overlaps(&self, c: &Cuboid) -> bool {
+        Self::keys(c)
+            .filter_map(|key| self.buckets.get(&key))
+            .flatten()
+            .any(|&i| {
+                let other = &self.cuboids[i];
+                (0..3).all(|a| {
+                    (c.center[a] - other.center[a]).abs() < c.half_size[a] + other.half_size[a]
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use brdb::{Brz, IntoReader, assets};
+
+    use super::*;
+
+    // a save with a plain brick and a rerouter, which has a component
+    fn save(component: bool) -> Vec<u8> {
+        let mut world = World::new();
+        world.add_brick(Brick {
+            position: (0, 0, 6).into(),
+            ..Default::default()
+        });
+        if component {
+            world.add_brick(
+                Brick {
+                    position: (10, 0, 1).into(),
+                    asset: assets::bricks::B_REROUTE,
+                    ..Default::default()
+                }
+                .with_component(assets::components::Rerouter),
+            );
+        }
+        world.to_brz_vec().unwrap()
+    }
+
+    fn read(bytes: &[u8], drop_components: bool) -> Result<World, Error> {
+        read_reader(
+            &Brz::read_slice(bytes).unwrap().into_reader(),
+            drop_components,
+        )
+    }
+
+    #[test]
+    fn components_are_not_dropped_silently() {
+        assert_eq!(read(&save(false), false).unwrap().bricks.len(), 1);
+        assert!(matches!(
+            read(&save(true), false),
+            Err(Error::UnmergeableSave {
+                components: 1,
+                wires: 0,
+                entities: 0
+            })
+        ));
+        assert_eq!(read(&save(true), true).unwrap().bricks.len(), 2);
+    }
+}
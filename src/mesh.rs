@@ -0,0 +1,416 @@
+use std::io::{self, Write};
+
+use brdb::Brick;
+
+use crate::{render::Cuboid, util::to_linear_rgb};
+
+// a triangle mesh where every face has its own vertices so it can have a flat color
+//
+// positions are in brickadia units, converted from brickadia's left handed z-up
+// coordinates into right handed z-up coordinates
+#[derive(Debug, Default, Clone)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    // srgb vertex colors
+    pub colors: Vec<[u8; 3]>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn from_bricks(bricks: &[Brick]) -> Self {
+        let cuboids = bricks.iter().map(Cuboid::from).collect::<Vec<_>>();
+        Self::from_cuboids(&cuboids)
+    }
+
+    pub fn from_cuboids(cuboids: &[Cuboid]) -> Self {
+        let mut mesh = Mesh {
+            positions: Vec::with_capacity(cuboids.len() * 24),
+            colors: Vec::with_capacity(cuboids.len() * 24),
+            indices: Vec::with_capacity(cuboids.len() * 36),
+        };
+
+        for c in cuboids {
+            // flip the y axis to get right handed coordinates
+            let center = [c.center[0], -c.center[1], c.center[2]];
+
+            for axis in 0..3 {
+                for sign in [-1.0, 1.0] {
+                    // the other two axes span the face, in counter clockwise order
+                    // when looking at the face from outside the box
+                    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                    let mut corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+                    if sign < 0.0 {
+                        corners.reverse();
+                    }
+
+                    let first = mesh.positions.len() as u32;
+                    for (su, sv) in corners {
+                        let mut p = center;
+                        p[axis] += sign * c.half_size[axis];
+                        p[u] += su * c.half_size[u];
+                        p[v] += sv * c.half_size[v];
+                        mesh.positions.push(p);
+                        mesh.colors.push(c.color);
+                    }
+                    mesh.indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+                }
+            }
+        }
+
+        mesh
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = [usize; 3]> + '_ {
+        self.indices
+            .chunks_exact(3)
+            .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+    }
+
+    // write a wavefront obj with colors appended to each vertex
+    pub fn write_obj(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(writer);
+        writeln!(writer, "# generated by heightmap2brz")?;
+        for (p, c) in self.positions.iter().zip(&self.colors) {
+            writeln!(
+                writer,
+                "v {} {} {} {:.4} {:.4} {:.4}",
+                p[0],
+                p[1],
+                p[2],
+                c[0] as f32 / 255.0,
+                c[1] as f32 / 255.0,
+                c[2] as f32 / 255.0
+            )?;
+        }
+        // obj indices start at 1
+        for [a, b, c] in self.triangles() {
+            writeln!(writer, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        writer.flush()
+    }
+
+    // write a binary stl, colors are stored in the attribute bytes as 15 bit rgb
+    pub fn write_stl(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(writer);
+        let mut header = [0u8; 80];
+        let name = b"heightmap2brz";
+        header[..name.len()].copy_from_slice(name);
+        writer.write_all(&header)?;
+        writer.write_all(&((self.indices.len() / 3) as u32).to_le_bytes())?;
+
+        for [a, b, c] in self.triangles() {
+            let [pa, pb, pc] = [self.positions[a], self.positions[b], self.positions[c]];
+            let e1: [f32; 3] = std::array::from_fn(|i| pb[i] - pa[i]);
+            let e2: [f32; 3] = std::array::from_fn(|i| pc[i] - pa[i]);
+            let n = [
+                e1[1] * e2[2] - e1[2] * e2[1],
+                e1[2] * e2[0] - e1[0] * e2[2],
+                e1[0] * e2[1] - e1[1] * e2[0],
+            ];
+            let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2])
+                .sqrt()
+                .max(f32::EPSILON);
+
+            for v in [n.map(|x| x / len), pa, pb, pc] {
+                for x in v {
+                    writer.write_all(&x.to_le_bytes())?;
+                }
+            }
+
+            // VisCAM/SolidView layout, red in the high bits and the top bit marks the color as valid
+            let [r, g, b] = self.colors[a].map(|x| (x >> 3) as u16);
+            writer.write_all(&(0x8000 | r << 10 | g << 5 | b).to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    // write a gltf document with the mesh data embedded as base64
+    pub fn write_gltf(&self, writer: &mut impl Write) -> io::Result<()> {
+        let (_, json) = self.gltf_parts(true);
+        writer.write_all(json.as_bytes())
+    }
+
+    // write a binary gltf
+    pub fn write_glb(&self, writer: &mut impl Write) -> io::Result<()> {
+        let (mut buffer, json) = self.gltf_parts(false);
+
+        // chunks are padded to 4 bytes, json with spaces and binary with zeros
+        let mut json = json.into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+        let len = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut writer = io::BufWriter::new(writer);
+        writer.write_all(b"glTF")?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(len as u32).to_le_bytes())?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(b"JSON")?;
+        writer.write_all(&json)?;
+        writer.write_all(&(buffer.len() as u32).to_le_bytes())?;
+        writer.write_all(b"BIN\0")?;
+        writer.write_all(&buffer)?;
+        writer.flush()
+    }
+
+    // build the binary buffer and json document of a gltf asset,
+    // optionally embedding the buffer in the document
+    fn gltf_parts(&self, embed: bool) -> (Vec<u8>, String) {
+        // gltf is y-up, so the z axis becomes y
+        let positions = self
+            .positions
+            .iter()
+            .map(|p| [p[0], p[2], -p[1]])
+            .collect::<Vec<_>>();
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for p in &positions {
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        if positions.is_empty() {
+            (min, max) = ([0.0; 3], [0.0; 3]);
+        }
+
+        let mut buffer = Vec::with_capacity(positions.len() * 16 + self.indices.len() * 4);
+        for p in &positions {
+            for x in p {
+                buffer.extend(x.to_le_bytes());
+            }
+        }
+        let colors_offset = buffer.len();
+        // gltf vertex colors are linear
+        for c in &self.colors {
+            buffer.extend(to_linear_rgb([c[0], c[1], c[2], 255]));
+        }
+        let indices_offset = buffer.len();
+        for i in &self.indices {
+            buffer.extend(i.to_le_bytes());
+        }
+
+        let uri = if embed {
+            format!(
+                r#","uri":"data:application/octet-stream;base64,{}""#,
+                base64(&buffer)
+            )
+        } else {
+            String::new()
+        };
+        let json = format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"heightmap2brz"}},"#,
+                r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"#,
+                r#""meshes":[{{"primitives":[{{"attributes":{{"POSITION":0,"COLOR_0":1}},"indices":2}}]}}],"#,
+                r#""accessors":["#,
+                r#"{{"bufferView":0,"componentType":5126,"count":{vertices},"type":"VEC3","min":[{min}],"max":[{max}]}},"#,
+                r#"{{"bufferView":1,"componentType":5121,"normalized":true,"count":{vertices},"type":"VEC4"}},"#,
+                r#"{{"bufferView":2,"componentType":5125,"count":{indices},"type":"SCALAR"}}],"#,
+                r#""bufferViews":["#,
+                r#"{{"buffer":0,"byteOffset":0,"byteLength":{colors_offset},"target":34962}},"#,
+                r#"{{"buffer":0,"byteOffset":{colors_offset},"byteLength":{colors_len},"target":34962}},"#,
+                r#"{{"buffer":0,"byteOffset":{indices_offset},"byteLength":{indices_len},"target":34963}}],"#,
+                r#""buffers":[{{"byteLength":{buffer_len}{uri}}}]}}"#
+            ),
+            vertices = positions.len(),
+            indices = self.indices.len(),
+            min = min.map(|x| x.to_string()).join(","),
+            max = max.map(|x| x.to_string()).join(","),
+            colors_offset = colors_offset,
+            colors_len = indices_offset - colors_offset,
+            indices_offset = indices_offset,
+            indices_len = buffer.len() - indices_offset,
+            buffer_len = buffer.len(),
+            uri = uri,
+        );
+
+        (buffer, json)
+    }
+}
+
+// standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[(n >> (18 - i * 6)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use brdb::{BrickSize, BrickType, Color, Position, assets::bricks::PB_DEFAULT_BRICK};
+
+    use super::*;
+
+    fn brick(position: (i32, i32, i32), size: (u16, u16, u16), color: [u8; 3]) -> Brick {
+        Brick {
+            asset: BrickType::Procedural {
+                asset: PB_DEFAULT_BRICK,
+                size: BrickSize::new(size.0, size.1, size.2),
+            },
+            position: Position::new(position.0, position.1, position.2),
+            color: Color {
+                r: color[0],
+                g: color[1],
+                b: color[2],
+            },
+            ..Default::default()
+        }
+    }
+
+    // a red brick and a blue brick on top of it, colors are linear so these stay pure
+    fn bricks() -> Vec<Brick> {
+        vec![
+            brick((0, 0, 6), (10, 20, 6), [255, 0, 0]),
+            brick((0, 0, 16), (5, 5, 4), [0, 0, 255]),
+        ]
+    }
+
+    fn normal(mesh: &Mesh, [a, b, c]: [usize; 3]) -> [f32; 3] {
+        let [pa, pb, pc] = [mesh.positions[a], mesh.positions[b], mesh.positions[c]];
+        let e1: [f32; 3] = std::array::from_fn(|i| pb[i] - pa[i]);
+        let e2: [f32; 3] = std::array::from_fn(|i| pc[i] - pa[i]);
+        [
+            e1[1] * e2[2] - e1[2] * e2[1],
+            e1[2] * e2[0] - e1[0] * e2[2],
+            e1[0] * e2[1] - e1[1] * e2[0],
+        ]
+    }
+
+    #[test]
+    fn counts() {
+        let mesh = Mesh::from_bricks(&bricks());
+        // 6 faces of 4 vertices and 2 triangles per brick
+        assert_eq!(mesh.positions.len(), 48);
+        assert_eq!(mesh.colors.len(), 48);
+        assert_eq!(mesh.indices.len(), 72);
+        assert!(
+            mesh.indices
+                .iter()
+                .all(|&i| (i as usize) < mesh.positions.len())
+        );
+    }
+
+    #[test]
+    fn faces_wind_outward() {
+        let bricks = bricks();
+        let mesh = Mesh::from_bricks(&bricks);
+        // the second half of the triangles belong to the second brick
+        for (i, t) in mesh.triangles().enumerate() {
+            let c = Cuboid::from(&bricks[i / 12]);
+            let center = [c.center[0], -c.center[1], c.center[2]];
+            let n = normal(&mesh, t);
+            let mid: [f32; 3] =
+                std::array::from_fn(|a| t.iter().map(|&v| mesh.positions[v][a]).sum::<f32>() / 3.0);
+            let out = (0..3).map(|a| n[a] * (mid[a] - center[a])).sum::<f32>();
+            assert!(out > 0.0, "triangle {i} faces inward");
+        }
+
+        // the top of the red brick faces up and is at the top of the brick
+        let top = mesh
+            .triangles()
+            .take(12)
+            .find(|&t| normal(&mesh, t)[2] > 0.0)
+            .unwrap();
+        assert!(top.iter().all(|&v| mesh.positions[v][2] == 12.0));
+    }
+
+    #[test]
+    fn obj() {
+        let mut out = vec![];
+        Mesh::from_bricks(&bricks()).write_obj(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines = |prefix: &str| text.lines().filter(|l| l.starts_with(prefix)).count();
+        assert_eq!(lines("v "), 48);
+        assert_eq!(lines("f "), 24);
+
+        // indices start at 1 and vertices carry the color
+        assert!(text.lines().any(|l| l == "f 1 2 3"));
+        assert!(
+            text.lines()
+                .all(|l| !l.starts_with("f ") || !l.contains(" 0"))
+        );
+        let first = text.lines().find(|l| l.starts_with("v ")).unwrap();
+        assert!(first.ends_with(" 1.0000 0.0000 0.0000"));
+    }
+
+    #[test]
+    fn stl_records() {
+        let mesh = Mesh::from_bricks(&bricks());
+        let mut out = vec![];
+        mesh.write_stl(&mut out).unwrap();
+
+        // 80 byte header, triangle count, then 50 bytes per triangle
+        assert_eq!(out.len(), 84 + 24 * 50);
+        assert!(out.starts_with(b"heightmap2brz"));
+        assert_eq!(u32::from_le_bytes(out[80..84].try_into().unwrap()), 24);
+
+        let float = |at: usize| f32::from_le_bytes(out[at..at + 4].try_into().unwrap());
+        for (i, t) in mesh.triangles().enumerate() {
+            let record = 84 + i * 50;
+            // a unit normal followed by the three corners
+            let n = normal(&mesh, t);
+            let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            for (a, n) in n.iter().enumerate() {
+                assert_eq!(float(record + a * 4), n / len);
+            }
+            for (corner, &v) in t.iter().enumerate() {
+                for (a, p) in mesh.positions[v].iter().enumerate() {
+                    assert_eq!(float(record + 12 + corner * 12 + a * 4), *p);
+                }
+            }
+
+            // VisCAM color, valid bit then 5 bits each of red, green and blue
+            let color = u16::from_le_bytes([out[record + 48], out[record + 49]]);
+            let expected = if i < 12 {
+                0x8000 | 31 << 10
+            } else {
+                0x8000 | 31
+            };
+            assert_eq!(color, expected, "triangle {i}");
+        }
+    }
+
+    #[test]
+    fn gltf_counts() {
+        let mesh = Mesh::from_bricks(&bricks());
+        let mut out = vec![];
+        mesh.write_gltf(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains(r#""componentType":5126,"count":48,"type":"VEC3""#));
+        assert!(json.contains(r#""componentType":5121,"normalized":true,"count":48"#));
+        assert!(json.contains(r#""componentType":5125,"count":72,"type":"SCALAR""#));
+        // 12 bytes of position and 4 of color per vertex, 4 bytes per index
+        let buffer = 48 * 16 + 72 * 4;
+        assert!(json.contains(&format!(r#""byteLength":{buffer},"uri""#)));
+
+        let mut glb = vec![];
+        mesh.write_glb(&mut glb).unwrap();
+        assert!(glb.starts_with(b"glTF"));
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+        let json_len = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        let bin = 20 + json_len;
+        assert_eq!(&glb[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(
+            u32::from_le_bytes(glb[bin..bin + 4].try_into().unwrap()) as usize,
+            buffer
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    f32::consts::FRAC_PI_2,
+    hash::{Hash, Hasher},
+    io::Cursor,
+};
+
+use brdb::{Brick, World};
+use image::{
+    Rgb, RgbImage, Rgba, RgbaImage,
+    codecs::jpeg::JpegEncoder,
+    imageops::{self, FilterType},
+};
+
+use crate::{
+    Error,
+    map::{Colormap, Mask, Source},
+    render::{Camera, render_bricks},
+    util::to_srgb,
+};
+
+pub const DEFAULT_DESCRIPTION: &str = "Save generated from heightmap file";
+
+// longest side of a save's preview image
+const SCREENSHOT_SIZE: u32 = 512;
+
+// the preview image shown for a save in the game
+#[derive(Clone)]
+pub enum Screenshot {
+    // the colormap, scaled down
+    Colormap,
+    // a top-down render of the generated bricks
+    Render,
+    // an image file or encoded image
+    Image(Source),
+    // no preview image, also removes the preview of a merged save
+    None,
+}
+
+impl Hash for Screenshot {
+    // image contents are hashed with the other input sources
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+    }
+}
+
+// the name, description, author and preview image of a generated save
+#[derive(Clone, Default, Hash)]
+pub struct SaveMeta {
+    pub name: Option<String>,
+    // the generator options are always added after the description
+    pub description: Option<String>,
+    pub author: Option<String>,
+    // defaults to the colormap for new saves and keeps the preview of merged saves
+    pub screenshot: Option<Screenshot>,
+}
+
+impl SaveMeta {
+    // the image input used for the preview, if any
+    pub fn screenshot_source(&self) -> Option<&Source> {
+        match &self.screenshot {
+            Some(Screenshot::Image(source)) => Some(source),
+            _ => None,
+        }
+    }
+
+    // write the metadata into a save, args are the command line that reproduces the bricks.
+    // a merged save only has the fields that were set replaced
+    pub fn apply(
+        &self,
+        world: &mut World,
+        args: &str,
+        merged: bool,
+        screenshot: Option<RgbaImage>,
+    ) -> Result<(), Error> {
+        let bundle = &mut world.meta.bundle;
+        if let Some(name) = &self.name {
+            bundle.name = name.clone();
+        }
+        if let Some(author) = &self.author {
+            bundle.authors = vec![author.clone()];
+        }
+
+        let description = match &self.description {
+            Some(description) => description.trim_end(),
+            None if merged => bundle.description.trim_end(),
+            None => DEFAULT_DESCRIPTION,
+        };
+        let line = format!(
+            "{} by heightmap {}: {args}",
+            if merged { "Bricks added" } else { "Generated" },
+            env!("CARGO_PKG_VERSION")
+        );
+        bundle.description = if description.is_empty() {
+            line
+        } else {
+            format!("{description}\n\n{line}")
+        };
+
+        match (screenshot, &self.screenshot) {
+            (Some(img), _) => world.meta.screenshot = Some(encode_screenshot(&img)?),
+            (None, Some(Screenshot::None)) => world.meta.screenshot = None,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// scale a map size so it fits in the preview, pixel is the half width and length of a pixel
+fn fit((width, height): (u32, u32), (size_x, size_y): (u16, u16)) -> (u32, u32) {
+    let (w, h) = (width as f64 * size_x as f64, height as f64 * size_y as f64);
+    let scale = SCREENSHOT_SIZE as f64 / w.max(h).max(f64::EPSILON);
+    (
+        (w * scale).round().max(1.0) as u32,
+        (h * scale).round().max(1.0) as u32,
+    )
+}
+
+// the colormap as it appears on the bricks, masked pixels are left transparent
+pub fn colormap_screenshot(
+    colormap: &dyn Colormap,
+    mask: Option<&dyn Mask>,
+    pixel: (u16, u16),
+) -> RgbaImage {
+    let (width, height) = colormap.size();
+    // brick colors are linear
+    let img = RgbaImage::from_fn(width, height, |x, y| {
+        if mask.is_some_and(|mask| !mask.at(x, y)) {
+            return Rgba([0, 0, 0, 0]);
+        }
+        Rgba(to_srgb(colormap.at(x, y)))
+    });
+    let (w, h) = fit((width, height), pixel);
+    imageops::resize(&img, w, h, FilterType::Triangle)
+}
+
+// a top-down render of bricks generated from a map of this size
+pub fn render_screenshot(bricks: &[Brick], size: (u32, u32), pixel: (u16, u16)) -> RgbaImage {
+    let camera = Camera {
+        yaw: 0.0,
+        pitch: FRAC_PI_2,
+        zoom: 1.0,
+    };
+    render_bricks(bricks, &camera, fit(size, pixel))
+}
+
+// shrink an image to fit the preview and encode it as a jpeg, transparency becomes black
+pub fn encode_screenshot(img: &RgbaImage) -> Result<Vec<u8>, Error> {
+    let (width, height) = img.dimensions();
+    let img = if width.max(height) > SCREENSHOT_SIZE {
+        let (w, h) = fit((width, height), (1, 1));
+        imageops::resize(img, w, h, FilterType::Triangle)
+    } else {
+        img.clone()
+    };
+    let rgb = RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = img.get_pixel(x, y).0;
+        Rgb([r, g, b].map(|c| (c as u16 * a as u16 / 255) as u8))
+    });
+
+    let mut bytes = Cursor::new(vec![]);
+    JpegEncoder::new_with_quality(&mut bytes, 85)
+        .encode_image(&rgb)
+        .map_err(Error::EncodeImage)?;
+    Ok(bytes.into_inner())
+}
@@ -0,0 +1,67 @@
+use image::{Rgba, RgbaImage};
+
+use crate::{quad::TileRect, util::to_srgb};
+
+// how tiles are colored in a preview
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreviewMode {
+    // the color the bricks will have in game
+    #[default]
+    Color,
+    // shade each tile by its height
+    Height,
+    // a random color for each tile to show how well tiles were merged
+    Random,
+}
+
+// pick a stable random looking color for a tile index
+fn random_color(i: usize) -> [u8; 4] {
+    let mut x = (i as u32).wrapping_add(1).wrapping_mul(0x9e3779b9);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85ebca6b);
+    x ^= x >> 13;
+    let [r, g, b, _] = x.to_le_bytes();
+    // keep the colors away from black so they are visible against culled tiles
+    [r | 0x40, g | 0x40, b | 0x40, 255]
+}
+
+// render optimized tiles into a top-down image, scaled down so neither side exceeds max_side
+pub fn render_tiles(
+    rects: &[TileRect],
+    (width, height): (u32, u32),
+    mode: PreviewMode,
+    max_side: u32,
+) -> RgbaImage {
+    let scale = width.max(height).div_ceil(max_side.max(1)).max(1);
+    let mut img = RgbaImage::new(width.div_ceil(scale), height.div_ceil(scale));
+
+    let min_height = rects.iter().map(|r| r.height).min().unwrap_or(0);
+    let max_height = rects.iter().map(|r| r.height).max().unwrap_or(0);
+
+    for (i, r) in rects.iter().enumerate() {
+        let color = match mode {
+            PreviewMode::Color => {
+                let [r, g, b, _] = to_srgb(r.color);
+                [r, g, b, 255]
+            }
+            PreviewMode::Height => {
+                let shade = if max_height > min_height {
+                    (32 + (r.height - min_height) as u64 * 223 / (max_height - min_height) as u64)
+                        as u8
+                } else {
+                    255
+                };
+                [shade, shade, shade, 255]
+            }
+            PreviewMode::Random => random_color(i),
+        };
+
+        for x in r.pos.0 / scale..(r.pos.0 + r.size.0).div_ceil(scale).min(img.width()) {
+            for y in r.pos.1 / scale..(r.pos.1 + r.size.1).div_ceil(scale).min(img.height()) {
+                img.put_pixel(x, y, Rgba(color));
+            }
+        }
+    }
+
+    img
+}
@@ -1,7 +1,10 @@
+use crate::Error;
+use crate::collision::{Channels, CollisionRules};
 use crate::map::*;
+use crate::strata::Layer;
 use crate::util::*;
 use brdb::{
-    Brick, BrickSize, BrickType, Collision, Color, Position,
+    Brick, BrickSize, BrickType, Color, Position,
     assets::materials::{GLOW, PLASTIC},
 };
 use log::info;
@@ -19,6 +22,9 @@ struct Tile {
     height: u32,
     neighbors: HashSet<u32>,
     parent: Option<usize>,
+    // skipped because it is black in the mask
+    masked: bool,
+    collision: Channels,
 }
 
 pub struct QuadTree {
@@ -27,14 +33,26 @@ pub struct QuadTree {
     height: u32,
 }
 
+// an optimized tile, the footprint of a column of bricks
+#[derive(Debug, Clone, Copy)]
+pub struct TileRect {
+    pub pos: (u32, u32),
+    pub size: (u32, u32),
+    pub color: [u8; 4],
+    pub height: u32,
+}
+
 impl Tile {
     // determine if another tile is similar in all properties
     fn similar_quad(&self, other: &Self) -> bool {
         self.size == other.size
             && self.color == other.color
             && self.height == other.height
+            && self.collision == other.collision
             && self.parent.is_none()
             && other.parent.is_none()
+            && !self.masked
+            && !other.masked
     }
 
     // determine if another tile is similar in all properties except potentially width or height as long as they are in a line
@@ -45,8 +63,18 @@ impl Tile {
         (is_vertical && self.size.0 == other.size.0 || is_horizontal && self.size.1 == other.size.1)
             && self.color == other.color
             && self.height == other.height
+            && self.collision == other.collision
             && self.parent.is_none()
             && other.parent.is_none()
+            && !self.masked
+            && !other.masked
+    }
+
+    // determine if this tile is removed from the output
+    fn is_culled(&self, options: &GenOptions) -> bool {
+        self.parent.is_some()
+            || self.masked
+            || options.cull && (self.height == 0 || self.color[3] == 0)
     }
 
     // merge a few tiles with this one
@@ -72,12 +100,28 @@ impl Tile {
 
 impl QuadTree {
     // create a heightmap grid from two images
-    pub fn new(heightmap: &dyn Heightmap, colormap: &dyn Colormap) -> Result<Self, String> {
+    pub fn new(
+        heightmap: &dyn Heightmap,
+        colormap: &dyn Colormap,
+        mask: Option<&dyn Mask>,
+    ) -> Result<Self, Error> {
         let (width, height) = heightmap.size();
 
         if colormap.size() != heightmap.size() {
-            return Err("Heightmap and colormap must have same dimensions".to_string());
+            return Err(Error::MismatchedColormap {
+                heightmap: heightmap.size(),
+                colormap: colormap.size(),
+            });
         }
+        if let Some(mask) = mask
+            && mask.size() != heightmap.size()
+        {
+            return Err(Error::MismatchedMask {
+                heightmap: heightmap.size(),
+                mask: mask.size(),
+            });
+        }
+        let masked = |x: i32, y: i32| mask.is_some_and(|m| !m.at(x as u32, y as u32));
 
         let mut tiles = Vec::with_capacity((width * height) as usize);
 
@@ -88,13 +132,20 @@ impl QuadTree {
                     index: (x + y * height as i32) as usize,
                     center: (x as u32, y as u32),
                     // store a set of the neighbor's heights with each tile
-                    // they will be joined when the tiles merge
+                    // they will be joined when the tiles merge,
+                    // masked neighbors count as 0 so the edge of the mask is filled down
                     neighbors: vec![(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
                         .into_iter()
                         .filter(|(x, y)| {
                             *x >= 0 && *x < width as i32 && *y >= 0 && *y < height as i32
                         })
-                        .map(|(x, y)| heightmap.at(x as u32, y as u32))
+                        .map(|(x, y)| {
+                            if masked(x, y) {
+                                0
+                            } else {
+                                heightmap.at(x as u32, y as u32)
+                            }
+                        })
                         .fold(HashSet::new(), |mut set, height| {
                             set.insert(height);
                             set
@@ -103,6 +154,8 @@ impl QuadTree {
                     color: colormap.at(x as u32, y as u32),
                     height: heightmap.at(x as u32, y as u32),
                     parent: None,
+                    masked: masked(x, y),
+                    collision: Channels::ALL,
                 })
             }
         }
@@ -114,10 +167,48 @@ impl QuadTree {
         })
     }
 
+    // give every tile the collision channels of its region, before optimizing
+    pub fn set_collision(
+        &mut self,
+        rules: &CollisionRules,
+        mask: Option<&dyn Mask>,
+    ) -> Result<(), Error> {
+        if let Some(mask) = mask
+            && mask.size() != self.size()
+        {
+            return Err(Error::MismatchedMask {
+                heightmap: self.size(),
+                mask: mask.size(),
+            });
+        }
+        for t in self.tiles.iter_mut() {
+            let in_mask = mask.is_some_and(|m| m.at(t.center.0, t.center.1));
+            t.collision = rules.at(t.height, in_mask);
+        }
+        Ok(())
+    }
+
     fn index(&self, x: u32, y: u32) -> usize {
         (y + x * self.height) as usize
     }
 
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // the tiles that will be converted into bricks
+    pub fn rects<'a>(&'a self, options: &'a GenOptions) -> impl Iterator<Item = TileRect> + 'a {
+        self.tiles
+            .iter()
+            .filter(|t| !t.is_culled(options))
+            .map(|t| TileRect {
+                pos: t.center,
+                size: t.size,
+                color: t.color,
+                height: t.height,
+            })
+    }
+
     // optimize bricks with size (level+1)
     pub fn quad_optimize_level(&mut self, level: u32) -> usize {
         let mut count = 0;
@@ -201,7 +292,7 @@ impl QuadTree {
     }
 
     // optimize by nearby bricks in line
-    pub fn line_optimize(&mut self, tile_scale: u32) -> usize {
+    pub fn line_optimize(&mut self, tile_scale: (u32, u32), max_size: u32) -> usize {
         let mut count = 0;
         for x in 0..self.width {
             for y in 0..self.height {
@@ -221,7 +312,7 @@ impl QuadTree {
                 while x + sx < self.width {
                     let i = self.index(x + sx, y);
                     let t = &self.tiles[i];
-                    if (sx + t.size.0) * tile_scale > 500 || !start.similar_line(t) {
+                    if (sx + t.size.0) * tile_scale.0 > max_size || !start.similar_line(t) {
                         break;
                     }
                     horiz_tiles.push(i);
@@ -232,7 +323,7 @@ impl QuadTree {
                 while y + sy < self.height {
                     let i = self.index(x, y + sy);
                     let t = &self.tiles[i];
-                    if (sy + t.size.1) * tile_scale > 500 || !start.similar_line(t) {
+                    if (sy + t.size.1) * tile_scale.1 > max_size || !start.similar_line(t) {
                         break;
                     }
                     vert_tiles.push(i);
@@ -258,10 +349,14 @@ impl QuadTree {
 
     // convert quadtree state into bricks
     pub fn into_bricks(&self, options: GenOptions) -> Vec<Brick> {
-        self.tiles
+        // keep the tallest brick a multiple of the height step
+        let step = options.height_step();
+        let max_height = options.max_brick_height - options.max_brick_height % step;
+        let mut bricks = self
+            .tiles
             .iter()
             .flat_map(|t| {
-                if t.parent.is_some() || options.cull && (t.height == 0 || t.color[3] == 0) {
+                if t.is_culled(&options) {
                     return vec![];
                 }
 
@@ -281,44 +376,50 @@ impl QuadTree {
                 }
 
                 let mut bricks = vec![];
+                let top = z;
                 // until we've made enough bricks to fill the height
-                // add a brick with a max height of 250
+                // add a brick with a max height of max_height
                 while desired_height > 0 {
                     // pick height for this brick
-
-                    let height =
-                        min(max(desired_height, if options.stud { 5 } else { 2 }), 250) as u16;
-                    let height = height + height % (if options.stud { 5 } else { 2 });
+                    let mut height = min(max(desired_height, step as i32), max_height as i32);
+
+                    // end the brick at the bottom of its layer so the next layer gets its own color
+                    let depth = (top - z) as u32;
+                    let (layer, layer_end) = match &options.strata {
+                        Some(strata) if !options.img => strata.layer(depth),
+                        _ => (Layer::Surface, None),
+                    };
+                    if let Some(end) = layer_end {
+                        let room = ((end - depth) / 2) as i32;
+                        height = min(height, max(room - room % step as i32, step as i32));
+                    }
+                    let height = height as u16;
+                    let height = height + height % step;
+                    let [r, g, b] = options
+                        .strata
+                        .and_then(|strata| strata.color(layer, options.lrgb))
+                        .unwrap_or([t.color[0], t.color[1], t.color[2]]);
 
                     bricks.push(Brick {
                         asset: BrickType::Procedural {
                             asset: options.asset.clone(),
                             size: BrickSize::new(
-                                t.size.0 as u16 * options.size,
-                                t.size.1 as u16 * options.size, // if it's a microbrick image, just use the block size so it's cubes
+                                t.size.0 as u16 * options.size.0,
+                                t.size.1 as u16 * options.size.1, // if it's a microbrick image, just use the block size so it's cubes
                                 if options.img && options.micro {
-                                    options.size
+                                    options.size.0
                                 } else {
                                     height
                                 },
                             ),
                         },
                         position: Position::new(
-                            (t.center.0 as i32 * 2 + t.size.0 as i32) * options.size as i32,
-                            (t.center.1 as i32 * 2 + t.size.1 as i32) * options.size as i32,
+                            (t.center.0 as i32 * 2 + t.size.0 as i32) * options.size.0 as i32,
+                            (t.center.1 as i32 * 2 + t.size.1 as i32) * options.size.1 as i32,
                             z - height as i32 + 2,
                         ),
-                        collision: Collision {
-                            player: !options.nocollide,
-                            weapon: !options.nocollide,
-                            interact: !options.nocollide,
-                            ..Default::default()
-                        },
-                        color: Color {
-                            r: t.color[0],
-                            g: t.color[1],
-                            b: t.color[2],
-                        },
+                        collision: t.collision.into(),
+                        color: Color { r, g, b },
                         owner_index: None,
                         material_intensity: 0,
                         material: if options.glow { GLOW } else { PLASTIC },
@@ -331,30 +432,73 @@ impl QuadTree {
                 }
                 bricks
             })
-            .collect()
+            .collect::<Vec<_>>();
+        self.place_bricks(&mut bricks, &options);
+        bricks
+    }
+
+    // move bricks from pixel coordinates to the chosen origin
+    fn place_bricks(&self, bricks: &mut [Brick], options: &GenOptions) {
+        let (mut x, mut y, mut z) = options.offset;
+
+        if options.anchor == Anchor::Center {
+            let mut center = (
+                (self.width * options.size.0 as u32) as i32,
+                (self.height * options.size.1 as u32) as i32,
+            );
+            // keep bricks on the stud grid
+            if options.snap {
+                center = (center.0 - center.0 % 10, center.1 - center.1 % 10);
+            }
+            x -= center.0;
+            y -= center.1;
+        }
+
+        if let Some(base_z) = options.base_z {
+            let bottom = bricks
+                .iter()
+                .map(|b| match &b.asset {
+                    BrickType::Procedural { size, .. } => b.position.z - size.z as i32,
+                    BrickType::Basic(_) => b.position.z,
+                })
+                .min()
+                .unwrap_or(0);
+            let mut shift = base_z - bottom;
+            // keep bricks on the plate grid without going below the base
+            if options.snap {
+                shift += (4 - shift.rem_euclid(4)) % 4;
+            }
+            z += shift;
+        }
+
+        for b in bricks {
+            b.position += Position::new(x, y, z);
+        }
     }
 }
 
-// Generate a heightmap with brick conservation optimizations
-pub fn gen_opt_heightmap<F: Fn(f32) -> bool>(
+// Build and optimize a quadtree from a heightmap and colormap
+pub fn optimize_heightmap<F: Fn(f32) -> bool>(
     heightmap: &dyn Heightmap,
     colormap: &dyn Colormap,
-    options: GenOptions,
+    mask: Option<&dyn Mask>,
+    collision_mask: Option<&dyn Mask>,
+    options: &GenOptions,
     progress_f: F,
-) -> Result<Vec<Brick>, String> {
+) -> Result<QuadTree, Error> {
     macro_rules! progress {
         ($e:expr) => {
             if !progress_f($e) {
-                return Err("Stopped by user".to_string());
+                return Err(Error::Stopped);
             }
         };
     }
+    options.validate()?;
     progress!(0.0);
 
     info!("Building initial quadtree");
-    let (width, height) = heightmap.size();
-    let area = width * height;
-    let mut quad = QuadTree::new(heightmap, colormap)?;
+    let mut quad = QuadTree::new(heightmap, colormap, mask)?;
+    quad.set_collision(&options.collision, collision_mask)?;
     progress!(0.2);
 
     let (prog_offset, prog_scale) = if options.quadtree {
@@ -362,8 +506,11 @@ pub fn gen_opt_heightmap<F: Fn(f32) -> bool>(
         let mut scale = 0;
 
         // loop until the bricks would be too wide or we stop optimizing bricks
-        while 2_i32.pow(scale + 1) * (options.size as i32) < 500 {
-            progress!(0.2 + 0.5 * (scale as f32 / (500.0 / (options.size as f32)).log2()));
+        // quads grow on both axes, so the larger cell size limits them
+        let max_size = options.max_brick_size as i32;
+        let cell_size = options.size.0.max(options.size.1);
+        while 2_i32.pow(scale + 1) * (cell_size as i32) < max_size {
+            progress!(0.2 + 0.5 * (scale as f32 / (max_size as f32 / (cell_size as f32)).log2()));
             let count = quad.quad_optimize_level(scale);
             if count == 0 {
                 break;
@@ -384,7 +531,10 @@ pub fn gen_opt_heightmap<F: Fn(f32) -> bool>(
     loop {
         i += 1;
 
-        let count = quad.line_optimize(options.size as u32);
+        let count = quad.line_optimize(
+            (options.size.0 as u32, options.size.1 as u32),
+            options.max_brick_size as u32,
+        );
         progress!(prog_offset + prog_scale * (i as f32 / 5.0).min(1.0));
 
         if count == 0 {
@@ -393,8 +543,32 @@ pub fn gen_opt_heightmap<F: Fn(f32) -> bool>(
         info!("  Removed {} bricks", count);
     }
 
-    progress!(0.95);
+    Ok(quad)
+}
+
+// Generate a heightmap with brick conservation optimizations
+pub fn gen_opt_heightmap<F: Fn(f32) -> bool>(
+    heightmap: &dyn Heightmap,
+    colormap: &dyn Colormap,
+    mask: Option<&dyn Mask>,
+    collision_mask: Option<&dyn Mask>,
+    options: GenOptions,
+    progress_f: F,
+) -> Result<Vec<Brick>, Error> {
+    let quad = optimize_heightmap(
+        heightmap,
+        colormap,
+        mask,
+        collision_mask,
+        &options,
+        &progress_f,
+    )?;
+    if !progress_f(0.95) {
+        return Err(Error::Stopped);
+    }
 
+    let (width, height) = heightmap.size();
+    let area = width * height;
     let bricks = quad.into_bricks(options);
     let brick_count = bricks.len();
     info!(
@@ -405,6 +579,8 @@ pub fn gen_opt_heightmap<F: Fn(f32) -> bool>(
         area as i32 - brick_count as i32,
     );
 
-    progress!(1.0);
+    if !progress_f(1.0) {
+        return Err(Error::Stopped);
+    }
     Ok(bricks)
 }
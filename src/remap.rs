@@ -0,0 +1,277 @@
+use std::{
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use crate::{Error, map::Heightmap};
+
+// changes applied to the heights before they are scaled into bricks
+#[derive(Debug, Clone)]
+pub struct Remap {
+    // heights outside this range are clamped to it
+    pub clamp: Option<(u32, u32)>,
+    // stretch the lowest and highest heights to this range
+    pub normalize: Option<(u32, u32)>,
+    // exponent applied between the lowest and highest heights,
+    // above 1 flattens lowlands and exaggerates peaks
+    pub gamma: f32,
+    pub curve: Option<Curve>,
+    // round heights down into this many evenly spaced steps
+    pub terrace: Option<u32>,
+}
+
+impl Default for Remap {
+    fn default() -> Self {
+        Remap {
+            clamp: None,
+            normalize: None,
+            gamma: 1.0,
+            curve: None,
+            terrace: None,
+        }
+    }
+}
+
+impl Remap {
+    pub fn is_identity(&self) -> bool {
+        self.clamp.is_none()
+            && self.normalize.is_none()
+            && self.gamma == 1.0
+            && self.curve.is_none()
+            && self.terrace.is_none()
+    }
+}
+
+impl Hash for Remap {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.clamp.hash(state);
+        self.normalize.hash(state);
+        self.gamma.to_bits().hash(state);
+        self.curve.hash(state);
+        self.terrace.hash(state);
+    }
+}
+
+// a piecewise-linear curve, both axes go from 0 at the lowest height to 1 at the highest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    points: Vec<(f32, f32)>,
+}
+
+impl Curve {
+    pub fn new(mut points: Vec<(f32, f32)>) -> Self {
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Curve { points }
+    }
+
+    // read a curve with one "input output" or "input,output" point per line, # starts a comment
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut points = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|p| !p.is_empty())
+                .map(|p| p.parse::<f32>().ok().filter(|v| v.is_finite()));
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(Some(x)), Some(Some(y)), None) => points.push((x, y)),
+                _ => {
+                    return Err(Error::InvalidCurve {
+                        path: path.to_path_buf(),
+                        line: i + 1,
+                    });
+                }
+            }
+        }
+        Ok(Curve::new(points))
+    }
+
+    // find the output of the curve at t, outside the points the nearest point is used
+    pub fn at(&self, t: f64) -> f64 {
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return t;
+        };
+        if t <= first.0 as f64 {
+            return first.1 as f64;
+        }
+        if t >= last.0 as f64 {
+            return last.1 as f64;
+        }
+        let i = self.points.partition_point(|p| p.0 as f64 <= t);
+        let [(x0, y0), (x1, y1)] =
+            [self.points[i - 1], self.points[i]].map(|(x, y)| (x as f64, y as f64));
+        if x1 == x0 {
+            y1
+        } else {
+            y0 + (y1 - y0) * (t - x0) / (x1 - x0)
+        }
+    }
+}
+
+impl Hash for Curve {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for (x, y) in &self.points {
+            x.to_bits().hash(state);
+            y.to_bits().hash(state);
+        }
+    }
+}
+
+// A heightmap with its heights remapped
+pub struct Remapped {
+    inner: Box<dyn Heightmap>,
+    remap: Remap,
+    // range of the clamped input heights
+    range: (u32, u32),
+    // range of the output heights
+    target: (u32, u32),
+}
+
+impl Remapped {
+    pub fn new(inner: Box<dyn Heightmap>, remap: Remap) -> Self {
+        let (w, h) = inner.size();
+        let mut range = (u32::MAX, 0);
+        for y in 0..h {
+            for x in 0..w {
+                let height = clamp(inner.at(x, y), remap.clamp);
+                range = (range.0.min(height), range.1.max(height));
+            }
+        }
+        if range.0 > range.1 {
+            range = (0, 0);
+        }
+        let target = remap.normalize.unwrap_or(range);
+        Remapped {
+            inner,
+            remap,
+            range,
+            target,
+        }
+    }
+
+    // the lowest and highest heights before remapping
+    pub fn range(&self) -> (u32, u32) {
+        self.range
+    }
+}
+
+fn clamp(height: u32, range: Option<(u32, u32)>) -> u32 {
+    match range {
+        Some((min, max)) => height.clamp(min, max.max(min)),
+        None => height,
+    }
+}
+
+impl Heightmap for Remapped {
+    fn at(&self, x: u32, y: u32) -> u32 {
+        let height = clamp(self.inner.at(x, y), self.remap.clamp);
+        let (lo, hi) = self.range;
+        let mut t = if hi > lo {
+            (height - lo) as f64 / (hi - lo) as f64
+        } else {
+            0.0
+        };
+
+        t = t.powf(self.remap.gamma.max(0.0) as f64);
+        if let Some(curve) = &self.remap.curve {
+            t = curve.at(t);
+        }
+        if let Some(steps) = self.remap.terrace {
+            t = if steps > 1 {
+                (t * steps as f64).floor().min(steps as f64 - 1.0) / (steps - 1) as f64
+            } else {
+                0.0
+            };
+        }
+
+        let (lo, hi) = self.target;
+        (lo as f64 + t.clamp(0.0, 1.0) * (hi as f64 - lo as f64))
+            .round()
+            .max(0.0) as u32
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.inner.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resample::HeightmapBuffer;
+
+    fn remapped(heights: Vec<u32>, remap: Remap) -> Vec<u32> {
+        let width = heights.len() as u32;
+        let map = Remapped::new(Box::new(HeightmapBuffer::rows(width, heights)), remap);
+        (0..width).map(|x| map.at(x, 0)).collect()
+    }
+
+    #[test]
+    fn curve_file_with_comments() {
+        let path = std::env::temp_dir().join("heightmap_curve_comments.txt");
+        fs::write(
+            &path,
+            "# input output\n1,0.5 # the top\n\n0 0\n  0.5, 0.1\n",
+        )
+        .unwrap();
+        let curve = Curve::from_file(&path).unwrap();
+        assert_eq!(curve, Curve::new(vec![(0.0, 0.0), (0.5, 0.1), (1.0, 0.5)]));
+        assert!((curve.at(0.25) - 0.05).abs() < 1e-6);
+        assert_eq!(curve.at(2.0), 0.5);
+
+        fs::write(&path, "0 0\n0.5 0.1 0.2\n").unwrap();
+        let err = Curve::from_file(&path).unwrap_err();
+        assert!(matches!(err, Error::InvalidCurve { line: 2, .. }));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn normalize_flat_map() {
+        // a flat map has no range to stretch, so every height goes to the bottom
+        let remap = Remap {
+            normalize: Some((10, 20)),
+            ..Default::default()
+        };
+        assert_eq!(remapped(vec![7; 4], remap), vec![10; 4]);
+    }
+
+    #[test]
+    fn clamp_normalize_gamma_curve_order() {
+        let remap = Remap {
+            clamp: Some((0, 100)),
+            normalize: Some((0, 1000)),
+            gamma: 2.0,
+            curve: Some(Curve::new(vec![(0.0, 0.0), (1.0, 0.5)])),
+            terrace: None,
+        };
+        // 200 is clamped before the range is found, 50 is squared to 0.25 and then halved
+        assert_eq!(
+            remapped(vec![0, 50, 100, 200], remap),
+            vec![0, 125, 500, 500]
+        );
+    }
+
+    #[test]
+    fn terrace_endpoints() {
+        let remap = Remap {
+            terrace: Some(3),
+            ..Default::default()
+        };
+        // the lowest and highest heights stay on the first and last steps
+        assert_eq!(
+            remapped(vec![0, 25, 50, 75, 100], remap.clone()),
+            vec![0, 0, 50, 100, 100]
+        );
+        let single = Remap {
+            terrace: Some(1),
+            ..remap
+        };
+        assert_eq!(remapped(vec![0, 50, 100], single), vec![0, 0, 0]);
+    }
+}
@@ -0,0 +1,222 @@
+use brdb::{Brick, BrickType, Direction, Rotation};
+use image::{Rgba, RgbaImage};
+
+use crate::util::to_srgb;
+
+// direction of the light shading the bricks
+const LIGHT: [f32; 3] = [0.32, 0.48, 0.82];
+
+// an axis aligned box with a color, the shape of an unrotated brick
+#[derive(Debug, Clone, Copy)]
+pub struct Cuboid {
+    pub center: [f32; 3],
+    pub half_size: [f32; 3],
+    pub color: [u8; 3],
+}
+
+impl From<&Brick> for Cuboid {
+    fn from(brick: &Brick) -> Self {
+        // basic bricks are drawn as a 1x1 brick
+        let (x, y, z) = match &brick.asset {
+            BrickType::Procedural { size, .. } => (size.x, size.y, size.z),
+            BrickType::Basic(_) => (5, 5, 6),
+        };
+        // the direction is the axis the top of the brick faces, and the
+        // rotation turns the brick around that axis
+        let (x, y) = match brick.rotation {
+            Rotation::Deg0 | Rotation::Deg180 => (x, y),
+            Rotation::Deg90 | Rotation::Deg270 => (y, x),
+        };
+        let (x, y, z) = match brick.direction {
+            Direction::XPositive | Direction::XNegative => (z, x, y),
+            Direction::YPositive | Direction::YNegative => (x, z, y),
+            _ => (x, y, z),
+        };
+        // brick colors are stored as linear rgb
+        let [r, g, b, _] = to_srgb([brick.color.r, brick.color.g, brick.color.b, 255]);
+        Cuboid {
+            center: [
+                brick.position.x as f32,
+                brick.position.y as f32,
+                brick.position.z as f32,
+            ],
+            half_size: [x as f32, y as f32, z as f32],
+            color: [r, g, b],
+        }
+    }
+}
+
+// an orbiting orthographic camera looking at the center of the bricks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    // rotation around the vertical axis in radians
+    pub yaw: f32,
+    // angle above the horizon in radians, pi/2 looks straight down
+    pub pitch: f32,
+    // magnification, 1 fits all bricks in the image
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            yaw: 35f32.to_radians(),
+            pitch: 40f32.to_radians(),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    // rotate a world space vector into view space (x right, y down, z towards the camera)
+    fn view(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let (sy, cy) = self.yaw.sin_cos();
+        let (sp, cp) = self.pitch.sin_cos();
+        let rx = x * cy - y * sy;
+        let ry = x * sy + y * cy;
+        [rx, ry * sp - z * cp, z * sp + ry * cp]
+    }
+}
+
+// signed area of the parallelogram between two triangle edges
+fn edge(a: [f32; 3], b: [f32; 3], p: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+// rasterize a triangle in screen space, keeping the pixels closest to the camera
+fn fill_triangle(
+    img: &mut RgbaImage,
+    depth: &mut [f32],
+    [a, b, c]: [[f32; 3]; 3],
+    color: Rgba<u8>,
+) {
+    let area = edge(a, b, [c[0], c[1]]);
+    if area.abs() < f32::EPSILON {
+        return;
+    }
+
+    let (width, height) = img.dimensions();
+    let x0 = a[0].min(b[0]).min(c[0]).floor().max(0.0) as u32;
+    let y0 = a[1].min(b[1]).min(c[1]).floor().max(0.0) as u32;
+    let x1 = (a[0].max(b[0]).max(c[0]).ceil().max(0.0) as u32).min(width);
+    let y1 = (a[1].max(b[1]).max(c[1]).ceil().max(0.0) as u32).min(height);
+
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let p = [x as f32 + 0.5, y as f32 + 0.5];
+            let w0 = edge(b, c, p) / area;
+            let w1 = edge(c, a, p) / area;
+            let w2 = edge(a, b, p) / area;
+            if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                continue;
+            }
+
+            let z = w0 * a[2] + w1 * b[2] + w2 * c[2];
+            let i = (y * width + x) as usize;
+            if z > depth[i] {
+                depth[i] = z;
+                img.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+// render bricks with a camera into an image
+pub fn render_bricks(bricks: &[Brick], camera: &Camera, size: (u32, u32)) -> RgbaImage {
+    let cuboids = bricks.iter().map(Cuboid::from).collect::<Vec<_>>();
+    render_cuboids(&cuboids, camera, size)
+}
+
+// render boxes with a camera into an image
+pub fn render_cuboids(
+    cuboids: &[Cuboid],
+    camera: &Camera,
+    (width, height): (u32, u32),
+) -> RgbaImage {
+    let mut img = RgbaImage::new(width, height);
+    if cuboids.is_empty() || width == 0 || height == 0 {
+        return img;
+    }
+
+    // find the bounds of all the boxes
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for c in cuboids {
+        for a in 0..3 {
+            min[a] = min[a].min(c.center[a] - c.half_size[a]);
+            max[a] = max[a].max(c.center[a] + c.half_size[a]);
+        }
+    }
+    let center: [f32; 3] = std::array::from_fn(|a| (min[a] + max[a]) / 2.0);
+
+    // fit the corners of the bounds into the image
+    let mut extent = [1f32; 2];
+    for corner in 0..8 {
+        let p = camera.view(std::array::from_fn(|a| {
+            let bound = if corner & (1 << a) == 0 {
+                min[a]
+            } else {
+                max[a]
+            };
+            bound - center[a]
+        }));
+        extent = [extent[0].max(p[0].abs()), extent[1].max(p[1].abs())];
+    }
+    let scale = (width as f32 / (extent[0] * 2.0)).min(height as f32 / (extent[1] * 2.0))
+        * 0.95
+        * camera.zoom;
+
+    let mut depth = vec![f32::MIN; (width * height) as usize];
+
+    for c in cuboids {
+        // draw each face of the box that is facing the camera
+        for axis in 0..3 {
+            for sign in [-1.0, 1.0] {
+                let mut normal = [0.0; 3];
+                normal[axis] = sign;
+                if camera.view(normal)[2] <= 0.0 {
+                    continue;
+                }
+
+                let light = 0.45 + 0.55 * (normal[axis] * LIGHT[axis]).max(0.0);
+                let color = Rgba([
+                    (c.color[0] as f32 * light) as u8,
+                    (c.color[1] as f32 * light) as u8,
+                    (c.color[2] as f32 * light) as u8,
+                    255,
+                ]);
+
+                // the other two axes span the face
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                let corners =
+                    [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(su, sv)| {
+                        let mut p = c.center;
+                        p[axis] += sign * c.half_size[axis];
+                        p[u] += su * c.half_size[u];
+                        p[v] += sv * c.half_size[v];
+                        let [x, y, z] = camera.view(std::array::from_fn(|a| p[a] - center[a]));
+                        [
+                            width as f32 / 2.0 + x * scale,
+                            height as f32 / 2.0 + y * scale,
+                            z,
+                        ]
+                    });
+
+                fill_triangle(
+                    &mut img,
+                    &mut depth,
+                    [corners[0], corners[1], corners[2]],
+                    color,
+                );
+                fill_triangle(
+                    &mut img,
+                    &mut depth,
+                    [corners[0], corners[2], corners[3]],
+                    color,
+                );
+            }
+        }
+    }
+
+    img
+}
@@ -0,0 +1,367 @@
+use std::{f64::consts::PI, str::FromStr};
+
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    Error,
+    map::{Colormap, ColormapPNG, Heightmap, Mask},
+};
+
+// how pixels are combined when resampling a map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    // average of every input pixel covered by an output pixel
+    Area,
+    Lanczos,
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(Filter::Nearest),
+            "bilinear" => Ok(Filter::Bilinear),
+            "bicubic" => Ok(Filter::Bicubic),
+            "area" => Ok(Filter::Area),
+            "lanczos" => Ok(Filter::Lanczos),
+            _ => Err(Error::UnknownFilter(s.to_string())),
+        }
+    }
+}
+
+// the resolution maps are resampled to before generating
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resize {
+    // an exact size in pixels
+    Pixels(u32, u32),
+    // the longest side in studs, keeping the aspect ratio
+    Studs(u32),
+}
+
+impl Resize {
+    // find the size in pixels for a map, size is the half width and length of a pixel in brick units
+    pub fn pixels(&self, (width, height): (u32, u32), (size_x, size_y): (u16, u16)) -> (u32, u32) {
+        match *self {
+            Resize::Pixels(w, h) => (w.max(1), h.max(1)),
+            Resize::Studs(studs) => {
+                // a pixel is size / 5 studs wide
+                let longest =
+                    (width as f64 * size_x as f64).max(height as f64 * size_y as f64) / 5.0;
+                let scale = studs as f64 / longest.max(f64::EPSILON);
+                (
+                    (width as f64 * scale).round().max(1.0) as u32,
+                    (height as f64 * scale).round().max(1.0) as u32,
+                )
+            }
+        }
+    }
+}
+
+// A heightmap stored as a buffer of heights
+pub struct HeightmapBuffer {
+    width: u32,
+    height: u32,
+    heights: Vec<u32>,
+}
+
+impl HeightmapBuffer {
+    // heights are stored row by row
+    pub(crate) fn new(width: u32, height: u32, heights: Vec<u32>) -> Self {
+        HeightmapBuffer {
+            width,
+            height,
+            heights,
+        }
+    }
+
+    // a heightmap from rows of heights, for tests
+    #[cfg(test)]
+    pub(crate) fn rows(width: u32, heights: Vec<u32>) -> Self {
+        HeightmapBuffer::new(width, heights.len() as u32 / width, heights)
+    }
+}
+
+impl Heightmap for HeightmapBuffer {
+    fn at(&self, x: u32, y: u32) -> u32 {
+        self.heights[(y * self.width + x) as usize]
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+// A mask stored as a buffer of flags
+pub struct MaskBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<bool>,
+}
+
+impl Mask for MaskBuffer {
+    fn at(&self, x: u32, y: u32) -> bool {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+// resample a heightmap to a new size, heights are filtered as f64 so large heights stay exact
+pub fn resample_heightmap(
+    heightmap: &dyn Heightmap,
+    (width, height): (u32, u32),
+    filter: Filter,
+) -> HeightmapBuffer {
+    let (w, h) = heightmap.size();
+    let src = pixels((w, h), |x, y| [heightmap.at(x, y) as f64]);
+    let dst = resize(&src, 1, (w, h), (width, height), filter);
+    let heights = dst
+        .iter()
+        .map(|h| h.round().clamp(0.0, u32::MAX as f64) as u32)
+        .collect();
+    HeightmapBuffer::new(width, height, heights)
+}
+
+// resample a colormap to a new size, colors are blended in linear space with premultiplied
+// alpha so transparent pixels do not bleed their color into their neighbors
+pub fn resample_colormap(
+    colormap: &dyn Colormap,
+    (width, height): (u32, u32),
+    filter: Filter,
+) -> ColormapPNG {
+    let (w, h) = colormap.size();
+    let src = pixels((w, h), |x, y| {
+        let [r, g, b, a] = colormap.at(x, y).map(|c| c as f64);
+        [r * a / 255.0, g * a / 255.0, b * a / 255.0, a]
+    });
+    let dst = resize(&src, 4, (w, h), (width, height), filter);
+    let img = RgbaImage::from_fn(width, height, |x, y| {
+        let p = (y * width + x) as usize * 4;
+        let a = dst[p + 3].clamp(0.0, 255.0);
+        let color = |c: f64| {
+            if a == 0.0 {
+                0
+            } else {
+                (c * 255.0 / a).round().clamp(0.0, 255.0) as u8
+            }
+        };
+        Rgba([
+            color(dst[p]),
+            color(dst[p + 1]),
+            color(dst[p + 2]),
+            a.round() as u8,
+        ])
+    });
+    // the colors are already linear
+    ColormapPNG::from_image(img, true)
+}
+
+// resample a mask to a new size, pixels that end up at least half covered are kept
+pub fn resample_mask(mask: &dyn Mask, (width, height): (u32, u32), filter: Filter) -> MaskBuffer {
+    let (w, h) = mask.size();
+    let src = pixels((w, h), |x, y| [if mask.at(x, y) { 1.0 } else { 0.0 }]);
+    let dst = resize(&src, 1, (w, h), (width, height), filter);
+    MaskBuffer {
+        width,
+        height,
+        pixels: dst.iter().map(|p| *p >= 0.5).collect(),
+    }
+}
+
+// collect the channels of every pixel into a row major buffer
+fn pixels<const N: usize>((w, h): (u32, u32), f: impl Fn(u32, u32) -> [f64; N]) -> Vec<f64> {
+    (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .flat_map(|(x, y)| f(x, y))
+        .collect()
+}
+
+// resize a w x h buffer of pixels one axis at a time
+fn resize(
+    src: &[f64],
+    channels: usize,
+    (w, h): (u32, u32),
+    (width, height): (u32, u32),
+    filter: Filter,
+) -> Vec<f64> {
+    let rows = resize_axis(src, channels, w, h, width, true, filter);
+    resize_axis(&rows, channels, width, h, height, false, filter)
+}
+
+// the source pixels and weights that make up each of len output pixels
+fn weights(from: u32, len: u32, filter: Filter) -> Vec<Vec<(u32, f64)>> {
+    let step = from as f64 / len as f64;
+    (0..len)
+        .map(|i| {
+            let (start, end) = (i as f64 * step, (i + 1) as f64 * step);
+            let center = (start + end) / 2.0;
+            let (support, kernel): (f64, fn(f64) -> f64) = match filter {
+                Filter::Nearest => {
+                    return vec![((center.floor() as u32).min(from - 1), 1.0)];
+                }
+                // box filter that weights every source pixel by how much of it an output pixel covers
+                Filter::Area => {
+                    return (start.floor() as u32..(end.ceil() as u32).min(from))
+                        .map(|j| (j, end.min(j as f64 + 1.0) - start.max(j as f64)))
+                        .filter(|(_, w)| *w > 0.0)
+                        .collect();
+                }
+                Filter::Bilinear => (1.0, |x| (1.0 - x.abs()).max(0.0)),
+                Filter::Bicubic => (2.0, catmull_rom),
+                Filter::Lanczos => (3.0, |x| lanczos(x, 3.0)),
+            };
+
+            // stretch the kernel when shrinking so every source pixel contributes
+            let scale = step.max(1.0);
+            let left = (center - support * scale).floor().max(0.0) as u32;
+            let right = ((center + support * scale).ceil() as u32).min(from);
+            (left..right)
+                .map(|j| (j, kernel((j as f64 + 0.5 - center) / scale)))
+                .collect()
+        })
+        .collect()
+}
+
+fn catmull_rom(x: f64) -> f64 {
+    let x = x.abs();
+    if x < 1.0 {
+        1.5 * x * x * x - 2.5 * x * x + 1.0
+    } else if x < 2.0 {
+        -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0
+    } else {
+        0.0
+    }
+}
+
+fn lanczos(x: f64, a: f64) -> f64 {
+    let sinc = |x: f64| {
+        if x == 0.0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        }
+    };
+    if x.abs() < a {
+        sinc(x) * sinc(x / a)
+    } else {
+        0.0
+    }
+}
+
+// resize the rows (horizontal) or columns (vertical) of a w x h buffer to len pixels
+fn resize_axis(
+    src: &[f64],
+    channels: usize,
+    w: u32,
+    h: u32,
+    len: u32,
+    horizontal: bool,
+    filter: Filter,
+) -> Vec<f64> {
+    let (from, lines) = if horizontal { (w, h) } else { (h, w) };
+    let (out_w, out_h) = if horizontal { (len, h) } else { (w, len) };
+    let mut out = vec![0.0; (out_w * out_h) as usize * channels];
+    let weights = weights(from, len, filter);
+
+    for line in 0..lines {
+        for (i, weights) in weights.iter().enumerate() {
+            let mut sum = [0f64; 4];
+            let mut total = 0.0;
+            for &(j, weight) in weights {
+                let (x, y) = if horizontal { (j, line) } else { (line, j) };
+                let p = (y * w + x) as usize * channels;
+                for c in 0..channels {
+                    sum[c] += src[p + c] * weight;
+                }
+                total += weight;
+            }
+
+            let (x, y) = if horizontal {
+                (i as u32, line)
+            } else {
+                (line, i as u32)
+            };
+            let p = (y * out_w + x) as usize * channels;
+            for c in 0..channels {
+                out[p + c] = sum[c] / total.max(f64::EPSILON);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 5] = [
+        Filter::Nearest,
+        Filter::Bilinear,
+        Filter::Bicubic,
+        Filter::Area,
+        Filter::Lanczos,
+    ];
+
+    #[test]
+    fn large_heights_stay_exact() {
+        // f32 can not hold odd numbers above 2^24
+        let height = (1 << 28) + 1;
+        let flat = HeightmapBuffer::rows(6, vec![height; 36]);
+        for filter in FILTERS {
+            for size in [(3, 3), (6, 6), (13, 9)] {
+                let resized = resample_heightmap(&flat, size, filter);
+                assert!(resized.heights.iter().all(|&h| h == height), "{filter:?}");
+            }
+        }
+
+        // halving a step between two large heights lands halfway between them
+        let step = HeightmapBuffer::rows(2, vec![height, height + 2]);
+        let resized = resample_heightmap(&step, (1, 1), Filter::Area);
+        assert_eq!(resized.heights, vec![height + 1]);
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_bleed() {
+        // an opaque red pixel next to a transparent green one
+        let img = RgbaImage::from_fn(2, 1, |x, _| {
+            Rgba(if x == 0 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 255, 0, 0]
+            })
+        });
+        let colormap = ColormapPNG::from_image(img, true);
+        for filter in [Filter::Bilinear, Filter::Area] {
+            let resized = resample_colormap(&colormap, (1, 1), filter);
+            let [r, g, b, a] = resized.at(0, 0);
+            assert_eq!([r, g, b], [255, 0, 0], "{filter:?}");
+            assert!((127..=128).contains(&a));
+        }
+
+        // fully transparent areas stay transparent
+        let clear = ColormapPNG::from_image(RgbaImage::new(4, 4), true);
+        let resized = resample_colormap(&clear, (2, 2), Filter::Lanczos);
+        assert_eq!(resized.at(1, 1), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn upsampling_keeps_the_range() {
+        let ramp = HeightmapBuffer::rows(4, vec![0, 100, 200, 300]);
+        for filter in [Filter::Nearest, Filter::Bilinear, Filter::Area] {
+            let resized = resample_heightmap(&ramp, (8, 1), filter);
+            assert_eq!(resized.heights.len(), 8);
+            assert!(
+                resized.heights.windows(2).all(|w| w[0] <= w[1]),
+                "{filter:?}"
+            );
+            assert_eq!(resized.heights[0], 0);
+            assert_eq!(resized.heights[7], 300);
+        }
+    }
+}
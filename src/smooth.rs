@@ -0,0 +1,199 @@
+use std::hash::{Hash, Hasher};
+
+use crate::{map::Heightmap, resample::HeightmapBuffer};
+
+// filters that remove noise from the heights before generating,
+// applied in the order despeckle, median, gaussian
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Smooth {
+    // flatten single pixels that are higher or lower than all of their neighbors
+    pub despeckle: bool,
+    // radius in pixels of a median filter
+    pub median: Option<u32>,
+    // standard deviation in pixels of a gaussian blur
+    pub gaussian: Option<f32>,
+    // also generate without the filters to report how many merges they enabled,
+    // this does not change the bricks
+    pub compare: bool,
+}
+
+impl Smooth {
+    pub fn is_identity(&self) -> bool {
+        !self.despeckle
+            && self.median.is_none_or(|r| r == 0)
+            && self.gaussian.is_none_or(|s| s <= 0.0)
+    }
+
+    // run the enabled filters over a heightmap, none when no filter is enabled
+    pub fn apply(&self, heightmap: &dyn Heightmap) -> Option<Box<dyn Heightmap>> {
+        let mut smoothed: Option<Box<dyn Heightmap>> = None;
+        if self.despeckle {
+            smoothed = Some(Box::new(despeckle(heightmap)));
+        }
+        if let Some(radius) = self.median.filter(|r| *r > 0) {
+            let inner = smoothed.as_deref().unwrap_or(heightmap);
+            smoothed = Some(Box::new(median(inner, radius)));
+        }
+        if let Some(sigma) = self.gaussian.filter(|s| *s > 0.0) {
+            let inner = smoothed.as_deref().unwrap_or(heightmap);
+            smoothed = Some(Box::new(gaussian(inner, sigma)));
+        }
+        smoothed
+    }
+}
+
+impl Hash for Smooth {
+    // comparing does not change the output
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.despeckle.hash(state);
+        self.median.hash(state);
+        self.gaussian.map(f32::to_bits).hash(state);
+    }
+}
+
+// the heights in a square around a pixel, clamped to the edges of the map
+fn window(heightmap: &dyn Heightmap, x: u32, y: u32, radius: u32) -> impl Iterator<Item = u32> {
+    let (w, h) = heightmap.size();
+    let (x0, x1) = (x.saturating_sub(radius), (x + radius).min(w - 1));
+    let (y0, y1) = (y.saturating_sub(radius), (y + radius).min(h - 1));
+    (y0..=y1).flat_map(move |y| (x0..=x1).map(move |x| heightmap.at(x, y)))
+}
+
+// compute a filter for every pixel up front, so lookups during generation are cheap
+fn filter(heightmap: &dyn Heightmap, f: impl Fn(u32, u32) -> u32) -> HeightmapBuffer {
+    let (width, height) = heightmap.size();
+    let heights = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| f(x, y))
+        .collect();
+    HeightmapBuffer::new(width, height, heights)
+}
+
+// replace single pixel spikes and pits with the median of their neighbors
+pub fn despeckle(inner: &dyn Heightmap) -> HeightmapBuffer {
+    let (w, h) = inner.size();
+    filter(inner, |x, y| {
+        let height = inner.at(x, y);
+        let mut neighbors = Vec::with_capacity(8);
+        for (dx, dy) in [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ] {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+            if nx >= 0 && ny >= 0 && nx < w as i64 && ny < h as i64 {
+                neighbors.push(inner.at(nx as u32, ny as u32));
+            }
+        }
+
+        let spike = neighbors.iter().all(|n| *n < height);
+        let pit = neighbors.iter().all(|n| *n > height);
+        if neighbors.is_empty() || !(spike || pit) {
+            return height;
+        }
+        neighbors.sort_unstable();
+        neighbors[neighbors.len() / 2]
+    })
+}
+
+// replace every height with the median of the pixels around it
+pub fn median(inner: &dyn Heightmap, radius: u32) -> HeightmapBuffer {
+    filter(inner, |x, y| {
+        let mut heights = window(inner, x, y, radius).collect::<Vec<_>>();
+        let mid = heights.len() / 2;
+        *heights.select_nth_unstable(mid).1
+    })
+}
+
+// blur the heights with a gaussian kernel
+pub fn gaussian(inner: &dyn Heightmap, sigma: f32) -> HeightmapBuffer {
+    let (width, height) = inner.size();
+    let sigma = sigma as f64;
+    let radius = (sigma * 3.0).ceil() as i64;
+    let kernel = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let total = kernel.iter().sum::<f64>();
+    let kernel = kernel.iter().map(|k| k / total).collect::<Vec<_>>();
+
+    // blur one axis at a time, clamping to the edges of the map
+    let blur = |len: u32, sample: &dyn Fn(u32) -> f64, i: u32| {
+        kernel
+            .iter()
+            .enumerate()
+            .map(|(k, weight)| {
+                let j = (i as i64 + k as i64 - radius).clamp(0, len as i64 - 1);
+                sample(j as u32) * weight
+            })
+            .sum::<f64>()
+    };
+    let mut rows = vec![0.0; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            rows[(y * width + x) as usize] = blur(width, &|x| inner.at(x, y) as f64, x);
+        }
+    }
+    let mut heights = vec![0; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let h = blur(height, &|y| rows[(y * width + x) as usize], y);
+            heights[(y * width + x) as usize] = h.round().max(0.0) as u32;
+        }
+    }
+
+    HeightmapBuffer::new(width, height, heights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heights(map: &dyn Heightmap) -> Vec<u32> {
+        let (width, height) = map.size();
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| map.at(x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn despeckle_flattens_spikes_and_pits() {
+        #[rustfmt::skip]
+        let map = HeightmapBuffer::rows(4, vec![
+            5, 5, 5, 5,
+            5, 90, 5, 5,
+            5, 5, 5, 1,
+            5, 5, 5, 5,
+        ]);
+        assert_eq!(heights(&despeckle(&map)), vec![5; 16]);
+
+        // pixels with an equal neighbor are left alone
+        let steps = vec![1, 1, 5, 5, 1, 1, 5, 5];
+        let map = HeightmapBuffer::rows(4, steps.clone());
+        assert_eq!(heights(&despeckle(&map)), steps);
+    }
+
+    #[test]
+    fn median_of_window() {
+        let map = HeightmapBuffer::rows(3, vec![9, 1, 1, 1, 1, 9, 1, 9, 9]);
+        assert_eq!(heights(&median(&map, 1)), vec![1, 1, 1, 1, 1, 9, 1, 9, 9]);
+    }
+
+    #[test]
+    fn filters_chain_in_order() {
+        let map = HeightmapBuffer::rows(3, vec![4, 4, 4, 4, 40, 4, 4, 4, 4]);
+        let smooth = Smooth {
+            despeckle: true,
+            gaussian: Some(1.0),
+            ..Default::default()
+        };
+        // the spike is removed before the blur can spread it
+        let smoothed = smooth.apply(&map).unwrap();
+        assert_eq!(heights(&*smoothed), vec![4; 9]);
+        assert!(Smooth::default().apply(&map).is_none());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{Error, util::to_linear_rgb};
+
+// a layer of ground in a column of bricks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    // the top of the column, colored by the colormap
+    Surface,
+    Subsoil,
+    // everything below the subsoil
+    Rock,
+}
+
+// colors for the ground under the surface of tall columns,
+// so cliffs show dirt and rock instead of the surface color all the way down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Strata {
+    // depth in units of the layer that uses the colormap
+    pub surface: u32,
+    // depth in units of the layer under the surface
+    pub subsoil: u32,
+    // sRGB colors, or linear with --lrgb like the colormap
+    pub subsoil_color: [u8; 3],
+    pub rock_color: [u8; 3],
+}
+
+impl Default for Strata {
+    fn default() -> Self {
+        Strata {
+            surface: 8,
+            subsoil: 40,
+            subsoil_color: [107, 76, 48],
+            rock_color: [112, 112, 112],
+        }
+    }
+}
+
+impl Strata {
+    // find the layer at a depth below the top of a column, and the depth where that layer ends
+    pub fn layer(&self, depth: u32) -> (Layer, Option<u32>) {
+        if depth < self.surface {
+            (Layer::Surface, Some(self.surface))
+        } else if depth < self.surface + self.subsoil {
+            (Layer::Subsoil, Some(self.surface + self.subsoil))
+        } else {
+            (Layer::Rock, None)
+        }
+    }
+
+    // the linear brick color of a layer, the surface color comes from the colormap
+    pub fn color(&self, layer: Layer, lrgb: bool) -> Option<[u8; 3]> {
+        let [r, g, b] = match layer {
+            Layer::Surface => return None,
+            Layer::Subsoil => self.subsoil_color,
+            Layer::Rock => self.rock_color,
+        };
+        if lrgb {
+            return Some([r, g, b]);
+        }
+        let [r, g, b, _] = to_linear_rgb([r, g, b, 255]);
+        Some([r, g, b])
+    }
+}
+
+// parse a hex color such as #6b4c30
+pub fn parse_color(s: &str) -> Result<[u8; 3], Error> {
+    let hex = s.trim().trim_start_matches('#');
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(Error::InvalidColor(s.to_string())),
+    }
+}
+
+// format a color as hex for parse_color
+pub fn hex_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
@@ -1,9 +1,145 @@
-use brdb::{BString, Brick, World};
+use brdb::{BString, Brick, Guid, Owner, World, assets::bricks::PB_DEFAULT_BRICK};
+use std::hash::Hash;
+
+use crate::{
+    Error,
+    blend::Blend,
+    collision::{Channels, CollisionRules},
+    map::{Decoding, Elevation},
+    remap::Remap,
+    resample::{Filter, Resize},
+    smooth::Smooth,
+    strata::{Strata, hex_color},
+};
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::Path;
+
+// where the map is placed relative to the world origin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Anchor {
+    // the top left corner of the map is at the origin
+    #[default]
+    Corner,
+    // the center of the map is at the origin
+    Center,
+}
+
+// rotation and mirroring applied to the input maps, flips happen before the rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Transform {
+    // clockwise quarter turns
+    pub rotate: u8,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        self.rotate.is_multiple_of(4) && !self.flip_x && !self.flip_y
+    }
+
+    // size of a map after it is transformed
+    pub fn size(&self, (w, h): (u32, u32)) -> (u32, u32) {
+        if self.rotate % 2 == 1 { (h, w) } else { (w, h) }
+    }
+
+    // find the coordinate in the original map of a coordinate in the transformed map
+    pub fn source(&self, (x, y): (u32, u32), (w, h): (u32, u32)) -> (u32, u32) {
+        let (x, y) = match self.rotate % 4 {
+            1 => (y, h - 1 - x),
+            2 => (w - 1 - x, h - 1 - y),
+            3 => (w - 1 - y, x),
+            _ => (x, y),
+        };
+        (
+            if self.flip_x { w - 1 - x } else { x },
+            if self.flip_y { h - 1 - y } else { y },
+        )
+    }
+}
+
+// a rectangle of pixels to keep from the input maps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    // the same region in a map of a different size
+    pub fn scaled(&self, (from_w, from_h): (u32, u32), (to_w, to_h): (u32, u32)) -> Crop {
+        if (from_w, from_h) == (to_w, to_h) {
+            return *self;
+        }
+        let scale = |v: u32, from: u32, to: u32, ceil: bool| {
+            let v = v as f64 * to as f64 / from.max(1) as f64;
+            (if ceil { v.ceil() } else { v.floor() } as u32).min(to)
+        };
+        let (x, y) = (
+            scale(self.x, from_w, to_w, false),
+            scale(self.y, from_h, to_h, false),
+        );
+        Crop {
+            x,
+            y,
+            width: scale(self.x + self.width, from_w, to_w, true).max(x + 1) - x,
+            height: scale(self.y + self.height, from_h, to_h, true).max(y + 1) - y,
+        }
+    }
+}
+
+// the owner generated bricks are attributed to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BrickOwner {
+    pub id: Guid,
+    pub name: String,
+}
+
+impl BrickOwner {
+    // an owner from a player's id, ownership tools match bricks by the id and not the name
+    pub fn new(name: Option<&str>, id: Guid) -> Self {
+        BrickOwner {
+            id,
+            name: name.unwrap_or("Heightmap").to_string(),
+        }
+    }
+}
+
+// parse a uuid like 01234567-89ab-cdef-0123-456789abcdef into a guid
+pub fn parse_guid(s: &str) -> Option<Guid> {
+    let hex = s.trim().replace('-', "");
+    if hex.len() != 32 {
+        return None;
+    }
+    let v = u128::from_str_radix(&hex, 16).ok()?;
+    Some(Guid {
+        a: (v >> 96) as u32,
+        b: (v >> 64) as u32,
+        c: (v >> 32) as u32,
+        d: v as u32,
+    })
+}
+
+// add an owner to a world, returns the owner index bricks should use
+pub fn add_owner(world: &mut World, owner: &BrickOwner) -> usize {
+    let (index, _) = world.owners.insert_full(
+        owner.id,
+        Owner {
+            user_id: owner.id,
+            user_name: owner.name.clone(),
+            display_name: owner.name.clone(),
+        },
+    );
+    // the public owner is always first when the world is written
+    index + 1
+}
 
+#[derive(Clone, Hash)]
 pub struct GenOptions {
-    pub size: u16,
+    // half the width and length of a pixel in brick units, 5 per stud
+    pub size: (u16, u16),
     pub scale: u32,
     pub asset: BString,
     pub cull: bool,
@@ -13,10 +149,250 @@ pub struct GenOptions {
     pub snap: bool,
     pub img: bool,
     pub glow: bool,
-    pub hdmap: bool,
+    // how heights are stored in the heightmap pixels
+    pub decoding: Decoding,
+    // how terrain-rgb and terrarium elevations become heights
+    pub elevation: Elevation,
+    // how each heightmap is combined with the ones before it, summed when empty
+    pub blends: Vec<Blend>,
     pub lrgb: bool,
-    pub nocollide: bool,
     pub quadtree: bool,
+    pub anchor: Anchor,
+    // moves every brick, in brick units
+    pub offset: (i32, i32, i32),
+    // z of the bottom of the lowest brick, otherwise a height of 0 is at z 0
+    pub base_z: Option<i32>,
+    // region of the input maps to generate, before the transform
+    pub crop: Option<Crop>,
+    pub transform: Transform,
+    // resample the maps to this resolution before generating
+    pub resize: Option<Resize>,
+    pub height_filter: Filter,
+    pub color_filter: Filter,
+    pub smooth: Smooth,
+    pub remap: Remap,
+    // largest brick size along x and y, 5 per stud
+    pub max_brick_size: u16,
+    // largest height of a single brick in a column
+    pub max_brick_height: u16,
+    // bricks are public when there is no owner
+    pub owner: Option<BrickOwner>,
+    pub collision: CollisionRules,
+    // color the ground under the surface of tall columns
+    pub strata: Option<Strata>,
+}
+
+// the command line defaults, one stud per pixel with default bricks
+impl Default for GenOptions {
+    fn default() -> Self {
+        GenOptions {
+            size: (5, 5),
+            scale: 1,
+            asset: PB_DEFAULT_BRICK,
+            cull: false,
+            tile: false,
+            micro: false,
+            stud: false,
+            snap: false,
+            img: false,
+            glow: false,
+            decoding: Decoding::Gray,
+            elevation: Elevation::default(),
+            blends: vec![],
+            lrgb: false,
+            quadtree: true,
+            anchor: Anchor::Corner,
+            offset: (0, 0, 0),
+            base_z: None,
+            crop: None,
+            transform: Transform::default(),
+            resize: None,
+            height_filter: Filter::Bilinear,
+            color_filter: Filter::Area,
+            smooth: Smooth::default(),
+            remap: Remap::default(),
+            max_brick_size: 500,
+            max_brick_height: 250,
+            owner: None,
+            collision: CollisionRules::default(),
+            strata: None,
+        }
+    }
+}
+
+impl GenOptions {
+    // check that the brick size limits can be built with the asset
+    pub fn validate(&self) -> Result<(), Error> {
+        let (max_size, max_height) = brick_limits(&self.asset);
+        let min_size = self.size.0.max(self.size.1);
+        if self.max_brick_size < min_size || self.max_brick_size > max_size {
+            return Err(Error::BrickLimit {
+                kind: "size",
+                value: self.max_brick_size,
+                min: min_size,
+                max: max_size,
+            });
+        }
+        let min_height = self.height_step();
+        if self.max_brick_height < min_height || self.max_brick_height > max_height {
+            return Err(Error::BrickLimit {
+                kind: "height",
+                value: self.max_brick_height,
+                min: min_height,
+                max: max_height,
+            });
+        }
+        Ok(())
+    }
+
+    // brick heights are multiples of this
+    pub fn height_step(&self) -> u16 {
+        if self.stud { 5 } else { 2 }
+    }
+
+    // the command line flags that reproduce these options, defaults are left out.
+    // only values the command line parses back are recorded, so options it can't express
+    // are missing: a --curve (its file isn't kept), sizes that aren't whole studs,
+    // other brick assets and quadtree being off
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        // micro brick sizes are already in studs, everything else has 5 units per stud
+        let per_stud = if self.micro && !self.tile { 1 } else { 5 };
+        let (x, y) = (self.size.0 / per_stud, self.size.1 / per_stud);
+        // -s only takes whole studs
+        let whole = self.size.0.is_multiple_of(per_stud) && self.size.1.is_multiple_of(per_stud);
+        if whole && x != y {
+            args.push(format!("-s {x},{y}"));
+        } else if whole && x != 1 {
+            args.push(format!("-s {x}"));
+        }
+        if self.scale != 1 {
+            args.push(format!("-v {}", self.scale));
+        }
+        for (enabled, flag) in [
+            (self.cull, "--cull"),
+            (self.tile, "--tile"),
+            (self.micro, "--micro"),
+            (self.stud, "--stud"),
+            (self.snap, "--snap"),
+            (self.lrgb, "--lrgb"),
+            (self.img, "--img"),
+            (self.glow, "--glow"),
+            (self.decoding == Decoding::Rgba32, "--hdmap"),
+            (self.anchor == Anchor::Center, "--center"),
+            (self.transform.flip_x, "--flip-x"),
+            (self.transform.flip_y, "--flip-y"),
+            (self.smooth.despeckle, "--despeckle"),
+        ] {
+            if enabled {
+                args.push(flag.to_string());
+            }
+        }
+        if !matches!(self.decoding, Decoding::Gray | Decoding::Rgba32) {
+            args.push(format!("--decode {}", self.decoding.name()));
+        }
+        if self.decoding.is_elevation() {
+            let Elevation { scale, sea_level } = self.elevation;
+            if scale != Elevation::default().scale {
+                args.push(format!("--elevation-scale {scale}"));
+            }
+            if sea_level != 0.0 {
+                args.push(format!("--sea-level {sea_level}"));
+            }
+        }
+        if !self.blends.is_empty() {
+            let blends = self.blends.iter().map(Blend::to_string).collect::<Vec<_>>();
+            args.push(format!("--blend {}", blends.join(",")));
+        }
+        if let Some(crop) = self.crop {
+            args.push(format!(
+                "--crop {},{},{},{}",
+                crop.x, crop.y, crop.width, crop.height
+            ));
+        }
+        if !self.transform.rotate.is_multiple_of(4) {
+            args.push(format!("--rotate {}", self.transform.rotate % 4 * 90));
+        }
+        match self.resize {
+            Some(Resize::Pixels(w, h)) => args.push(format!("--resize {w}x{h}")),
+            Some(Resize::Studs(studs)) => args.push(format!("--target-studs {studs}")),
+            None => {}
+        }
+        if self.resize.is_some() {
+            args.push(format!("--height-filter {:?}", self.height_filter).to_lowercase());
+            args.push(format!("--color-filter {:?}", self.color_filter).to_lowercase());
+        }
+        if let Some(radius) = self.smooth.median {
+            args.push(format!("--median {radius}"));
+        }
+        if let Some(sigma) = self.smooth.gaussian {
+            args.push(format!("--gaussian {sigma}"));
+        }
+        if let Some((min, max)) = self.remap.clamp {
+            args.push(format!("--clamp {min},{max}"));
+        }
+        if let Some((min, max)) = self.remap.normalize {
+            args.push(format!("--normalize {min},{max}"));
+        }
+        if self.remap.gamma != 1.0 {
+            args.push(format!("--gamma {}", self.remap.gamma));
+        }
+        if let Some(steps) = self.remap.terrace {
+            args.push(format!("--terrace {steps}"));
+        }
+        if self.max_brick_size != 500 {
+            args.push(format!("--max-size {}", self.max_brick_size));
+        }
+        if self.max_brick_height != 250 {
+            args.push(format!("--max-height {}", self.max_brick_height));
+        }
+        if self.offset != (0, 0, 0) {
+            let (x, y, z) = self.offset;
+            args.push(format!("--offset {x},{y},{z}"));
+        }
+        if let Some(z) = self.base_z {
+            args.push(format!("--base-z {z}"));
+        }
+        let collision = self.collision;
+        if collision.channels == Channels::NOCOLLIDE {
+            args.push("--nocollide".to_string());
+        } else if collision.channels != Channels::ALL {
+            args.push(format!("--collision {}", collision.channels));
+        }
+        if let Some((height, channels)) = collision.below {
+            args.push(format!("--collision-below {height}={channels}"));
+        }
+        if let Some((height, channels)) = collision.above {
+            args.push(format!("--collision-above {height}={channels}"));
+        }
+        if collision.mask != Channels::NONE {
+            args.push(format!("--collision-mask-channels {}", collision.mask));
+        }
+        if let Some(strata) = self.strata {
+            args.push(format!("--strata {},{}", strata.surface, strata.subsoil));
+            args.push(format!(
+                "--subsoil-color {}",
+                hex_color(strata.subsoil_color)
+            ));
+            args.push(format!("--rock-color {}", hex_color(strata.rock_color)));
+        }
+        if let Some(owner) = &self.owner {
+            args.push(format!("--owner-name \"{}\"", owner.name));
+            args.push(format!("--owner-id {}", owner.id.uuid()));
+        }
+        args
+    }
+}
+
+// the largest size and height of a brick the game accepts for an asset
+pub fn brick_limits(asset: &BString) -> (u16, u16) {
+    match asset.as_ref() {
+        "PB_DefaultBrick" | "PB_DefaultTile" | "PB_DefaultStudded" | "PB_DefaultMicroBrick" => {
+            (500, 500)
+        }
+        // be conservative with other procedural bricks
+        _ => (500, 250),
+    }
 }
 
 // convert gamma to linear gamma
@@ -39,16 +415,82 @@ pub fn to_linear_rgb(rgb: [u8; 4]) -> [u8; 4] {
     ]
 }
 
+// convert linear gamma to gamma
+pub fn to_srgb_gamma(c: u8) -> u8 {
+    let cf = (c as f64) / 255.0;
+    (if cf > 0.0031308 {
+        (cf.powf(1.0 / 2.4) - 0.0521327) * 1.055 * 255.0
+    } else {
+        cf * 12.192 * 255.0
+    })
+    .round()
+    .clamp(0.0, 255.0) as u8
+}
+
+// convert linear rgb to sRGB
+pub fn to_srgb(rgb: [u8; 4]) -> [u8; 4] {
+    [
+        to_srgb_gamma(rgb[0]),
+        to_srgb_gamma(rgb[1]),
+        to_srgb_gamma(rgb[2]),
+        rgb[3],
+    ]
+}
+
 // given an array of bricks, create a save
-pub fn bricks_to_save(bricks: Vec<Brick>) -> World {
+pub fn bricks_to_save(mut bricks: Vec<Brick>, owner: Option<&BrickOwner>) -> World {
     let mut world = World::new();
+    if let Some(owner) = owner {
+        let index = add_owner(&mut world, owner);
+        for brick in &mut bricks {
+            brick.owner_index = Some(index);
+        }
+    }
     world.add_bricks(bricks);
-    world.meta.bundle.description = "Save generated from heightmap file".to_string();
     world
 }
 
 // get extension from filename
 #[allow(unused)]
-pub fn file_ext(filename: &PathBuf) -> Option<&str> {
+pub fn file_ext(filename: &Path) -> Option<&str> {
     filename.extension().and_then(OsStr::to_str)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remap::Curve;
+
+    #[test]
+    fn args_only_record_what_parses_back() {
+        let args = |options: GenOptions| options.to_args().join(" ");
+        assert_eq!(args(GenOptions::default()), "");
+        assert_eq!(
+            args(GenOptions {
+                size: (10, 15),
+                ..Default::default()
+            }),
+            "-s 2,3"
+        );
+        // 1.2 studs can't be given to -s
+        assert_eq!(
+            args(GenOptions {
+                size: (6, 6),
+                ..Default::default()
+            }),
+            ""
+        );
+        assert_eq!(
+            args(GenOptions {
+                size: (3, 3),
+                micro: true,
+                ..Default::default()
+            }),
+            "-s 3 --micro"
+        );
+        // the curve file isn't kept
+        let mut options = GenOptions::default();
+        options.remap.curve = Some(Curve::new(vec![(0.0, 0.0), (1.0, 0.5)]));
+        assert_eq!(args(options), "");
+    }
+}